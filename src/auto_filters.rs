@@ -2,44 +2,76 @@ use std::error::Error;
 use std::ffi::OsStr;
 use std::fs;
 use std::fs::ReadDir;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::error::Error as ManageError;
+use crate::glob_utils::{check_glob, glob_match};
 use crate::my_utils::get_linux_hostname;
 use crate::user_config::{Conf, Target};
 
 pub type FoundResult = Result<Vec<PathBuf>, Box<dyn Error>>;
 
-#[derive(Debug)]
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum FilterType {
+    #[default]
     Keyfile,
     Hostname,
 }
 
-fn pattern_pred(val: &OsStr, pat: &str) -> Result<bool, Box<dyn Error>> {
-    if pat.starts_with('*') {
-        Ok(val.to_str().unwrap().ends_with(&pat.replace("*", "")))
-    } else if pat.ends_with('*') {
-        Ok(val.to_str().unwrap().starts_with(&pat.replace("*", "")))
-    } else {
-        Err(Box::from("bad filter"))
+// the last pattern to match a name decides, patterns starting with ! take
+// the name back out
+fn pattern_pred(
+    name: &str,
+    file_patterns: &[String],
+) -> Result<bool, Box<dyn Error>> {
+    let mut found = false;
+
+    for pat in file_patterns {
+        if let Some(negated) = pat.strip_prefix('!') {
+            if found && glob_match(negated, name)? {
+                found = false;
+            }
+        } else if !found && glob_match(pat, name)? {
+            found = true;
+        }
     }
+
+    Ok(found)
 }
 
-fn get_dirs(read_dir: ReadDir, file_patterns: &[String]) -> FoundResult {
+pub fn get_dirs(
+    read_dir: ReadDir,
+    file_patterns: &[String],
+    file_regex: &[Regex],
+) -> FoundResult {
+    for pat in file_patterns {
+        check_glob(pat.strip_prefix('!').unwrap_or(pat))
+            .map_err(|err| format!("bad filter {}", err))?;
+    }
+
     let mut to_ret: Vec<PathBuf> = vec![];
 
     for dir in read_dir {
         let dir = dir?;
         let dir_path = dir.path();
-        let file_name = dir_path.file_name().ok_or("cant get file name")?;
-
-        for pat in file_patterns {
-            if pattern_pred(&file_name, &pat)? {
-                to_ret.push(dir_path.clone());
-            }
+        let file_name = dir_path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or("cant get file name")?;
+
+        if pattern_pred(file_name, file_patterns)?
+            || file_regex.iter().any(|reg| reg.is_match(file_name))
+        {
+            to_ret.push(dir_path.clone());
         }
     }
 
+    to_ret.sort();
+
     if to_ret.is_empty() {
         Err(Box::from("didn't find any under dirs"))
     } else {
@@ -47,34 +79,122 @@ fn get_dirs(read_dir: ReadDir, file_patterns: &[String]) -> FoundResult {
     }
 }
 
-fn hostname_filter(under_dir: &PathBuf, to_filter: &[Target]) -> FoundResult {
+// find the one target with the given name
+fn select_target<'a>(
+    name: &str,
+    to_filter: &'a [Target],
+) -> Result<&'a Target, Box<dyn Error>> {
+    let target: Vec<&Target> =
+        to_filter.iter().filter(|targ| targ.name == name).collect();
+
+    match target.len() {
+        1 => Ok(target[0]),
+        0 => Err(Box::from(format!("no target named {} in config", name))),
+        _ => Err(Box::from(format!(
+            "more then one filter found for {} in config",
+            name
+        ))),
+    }
+}
+
+fn hostname_target(to_filter: &[Target]) -> Result<&Target, Box<dyn Error>> {
     let hostname = get_linux_hostname().ok_or("cant get host name")?;
 
-    let target: Vec<&Target> = to_filter
-        .iter()
-        .filter(|targ| targ.name == hostname)
-        .collect();
+    select_target(&hostname, to_filter)
+}
+
+// the package dirs in under_dir the target picks
+fn target_dirs(under_dir: &Path, target: &Target) -> FoundResult {
+    let read_under = fs::read_dir(under_dir)
+        .map_err(ManageError::io_at("cant read under_dir", under_dir))?;
+
+    Ok(
+        get_dirs(read_under, &target.file_patterns, &target.file_regex)
+            .map_err(filter_error(Some(under_dir)))?,
+    )
+}
+
+fn hostname_filter(under_dir: &Path, to_filter: &[Target]) -> FoundResult {
+    let target: &Target =
+        hostname_target(to_filter).map_err(filter_error(None))?;
+
+    target_dirs(under_dir, target)
+}
 
-    if target.len() != 1 {
-        return Err(Box::from("more then one filter found for host in config"));
+// the keyfile holds the name of the target to use for this machine
+fn keyfile_target<'a>(
+    keyfile: &Path,
+    to_filter: &'a [Target],
+) -> Result<&'a Target, Box<dyn Error>> {
+    if !keyfile.is_file() {
+        return Err(Box::from("keyfile missing"));
     }
 
-    let target: &Target = target.first().expect("cant get filter");
+    let key_string = fs::read_to_string(keyfile)
+        .map_err(|err| format!("cant read keyfile {}", err))?;
+
+    let key_name = key_string.trim();
+
+    if key_name.is_empty() {
+        return Err(Box::from("keyfile is empty"));
+    }
+
+    select_target(key_name, to_filter)
+        .map_err(|err| Box::from(format!("bad keyfile: {}", err)))
+}
+
+fn keyfile_filter(
+    under_dir: &Path,
+    keyfile: &Path,
+    to_filter: &[Target],
+) -> FoundResult {
+    let target: &Target = keyfile_target(keyfile, to_filter)
+        .map_err(filter_error(Some(keyfile)))?;
 
-    let read_under = fs::read_dir(under_dir)?;
+    target_dirs(under_dir, target)
+}
 
-    get_dirs(read_under, &target.file_patterns)
+// the target this machine uses by the configs filter type
+pub fn machine_target(conf: &Conf) -> Result<&Target, ManageError> {
+    match conf.filter_type {
+        FilterType::Keyfile => keyfile_target(&conf.keyfile, &conf.to_filter)
+            .map_err(filter_error(Some(&conf.keyfile))),
+        FilterType::Hostname => {
+            hostname_target(&conf.to_filter).map_err(filter_error(None))
+        }
+    }
 }
 
-pub fn filter_target_dirs(under_dir: &PathBuf, conf: &Conf) -> FoundResult {
+pub fn filter_target_dirs(
+    under_dir: &Path,
+    conf: &Conf,
+) -> Result<Vec<PathBuf>, ManageError> {
     match conf.filter_type {
-        FilterType::Keyfile => unimplemented!(),
+        FilterType::Keyfile => {
+            keyfile_filter(under_dir, &conf.keyfile, &conf.to_filter)
+        }
         FilterType::Hostname => hostname_filter(under_dir, &conf.to_filter),
     }
+    .map_err(filter_error(None))
+}
+
+// errors that are only a message become filter errors about the path
+fn filter_error(
+    path: Option<&Path>,
+) -> impl FnOnce(Box<dyn Error>) -> ManageError + '_ {
+    move |err| {
+        ManageError::or_kind(err, |message| ManageError::Filter {
+            path: path.map(Path::to_owned),
+            message,
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
     use tempfile::tempdir;
 
     use super::*;
@@ -102,7 +222,7 @@ mod test {
     // TODO: find edge cases
     fn setup_temp<Y, T>(fake_data: Y, test: T)
     where
-        Y: FnOnce(&PathBuf) -> TestData,
+        Y: FnOnce(&Path) -> TestData,
         T: FnOnce(&PathBuf, &PathBuf),
     {
         let test_dir = tempdir().unwrap();
@@ -117,25 +237,23 @@ mod test {
 
         for dir in dirs_to_make {
             if let Err(err) = fs::create_dir_all(&dir) {
-                assert!(false, "dir {:?} make error {}", dir, err);
+                panic!("dir {:?} make error {}", dir, err);
             }
         }
 
         for file in files_to_make {
             if let Err(err) = fs::File::create(&file) {
-                assert!(false, "file {:?} make error {}", file, err);
+                panic!("file {:?} make error {}", file, err);
             }
         }
 
         assert!(check_path.exists(), "tempdir fails");
 
         test(&temp_path, &check_path);
-
-        assert!(true, "idk how this would fail");
     }
 
-    fn fake_under_data_start(system_dir: &PathBuf) -> TestData {
-        let dirs_to_make: Vec<PathBuf> = vec![
+    fn fake_under_data_start(system_dir: &Path) -> TestData {
+        let dirs_to_make: Vec<PathBuf> = [
             "under_dir/all_fake_zsh",
             "under_dir/all_fake_i3",
             "under_dir/O_fake_polybar",
@@ -152,8 +270,8 @@ mod test {
         TestData::new(dirs_to_make, vec![], check_path)
     }
 
-    fn hand_made_data_start(system_dir: &PathBuf) -> Vec<PathBuf> {
-        vec![
+    fn hand_made_data_start(system_dir: &Path) -> Vec<PathBuf> {
+        [
             "under_dir/all_fake_zsh",
             "under_dir/all_fake_i3",
             "under_dir/O_fake_polybar",
@@ -164,8 +282,8 @@ mod test {
         .collect()
     }
 
-    fn fake_under_data_end(system_dir: &PathBuf) -> TestData {
-        let dirs_to_make: Vec<PathBuf> = vec![
+    fn fake_under_data_end(system_dir: &Path) -> TestData {
+        let dirs_to_make: Vec<PathBuf> = [
             "under_dir/fake_zsh_all",
             "under_dir/fake_i3_all",
             "under_dir/fake_polybar_M",
@@ -182,8 +300,8 @@ mod test {
         TestData::new(dirs_to_make, vec![], check_path)
     }
 
-    fn hand_made_data_end(system_dir: &PathBuf) -> Vec<PathBuf> {
-        vec![
+    fn hand_made_data_end(system_dir: &Path) -> Vec<PathBuf> {
+        [
             "under_dir/fake_zsh_all",
             "under_dir/fake_i3_all",
             "under_dir/fake_polybar_M",
@@ -202,11 +320,13 @@ mod test {
                 Err(err) => panic!("{}", err),
             };
 
-            let hand_made_data = hand_made_data_end(&temp_path);
+            let hand_made_data = hand_made_data_end(temp_path);
 
-            if let Ok(found_vec) =
-                get_dirs(read_under, &["*_all".to_string(), "*_M".to_string()])
-            {
+            if let Ok(found_vec) = get_dirs(
+                read_under,
+                &["*_all".to_string(), "*_M".to_string()],
+                &[],
+            ) {
                 for found in found_vec {
                     assert!(
                         hand_made_data.contains(&found),
@@ -214,7 +334,7 @@ mod test {
                     )
                 }
             } else {
-                assert!(false, "get_dirs failed");
+                panic!("get_dirs failed");
             }
         })
     }
@@ -227,11 +347,13 @@ mod test {
                 Err(err) => panic!("{}", err),
             };
 
-            let hand_made_data = hand_made_data_start(&temp_path);
+            let hand_made_data = hand_made_data_start(temp_path);
 
-            if let Ok(found_vec) =
-                get_dirs(read_under, &["all_*".to_string(), "O_*".to_string()])
-            {
+            if let Ok(found_vec) = get_dirs(
+                read_under,
+                &["all_*".to_string(), "O_*".to_string()],
+                &[],
+            ) {
                 for found in found_vec {
                     assert!(
                         hand_made_data.contains(&found),
@@ -239,7 +361,164 @@ mod test {
                     )
                 }
             } else {
-                assert!(false, "get_dirs failed");
+                panic!("get_dirs failed");
+            }
+        })
+    }
+
+    fn fake_targets() -> Vec<Target> {
+        vec![
+            Target {
+                name: "Monolith".to_string(),
+                file_patterns: vec!["*_all".to_string(), "*_M".to_string()],
+                file_regex: vec![],
+                vars: BTreeMap::new(),
+            },
+            Target {
+                name: "Odimm".to_string(),
+                file_patterns: vec!["*_all".to_string(), "*_O".to_string()],
+                file_regex: vec![],
+                vars: BTreeMap::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_keyfile_filter() {
+        setup_temp(fake_under_data_end, |temp_path, under_dir| {
+            let keyfile = temp_path.join("keyfile");
+
+            if let Err(err) = fs::write(&keyfile, "Monolith\n") {
+                panic!("{}", err);
+            }
+
+            let hand_made_data = hand_made_data_end(temp_path);
+
+            match keyfile_filter(under_dir, &keyfile, &fake_targets()) {
+                Ok(found_vec) => {
+                    assert_eq!(found_vec.len(), hand_made_data.len());
+
+                    for found in found_vec {
+                        assert!(
+                            hand_made_data.contains(&found),
+                            "didn't find the right dirs"
+                        )
+                    }
+                }
+                Err(err) => panic!("keyfile_filter failed {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_keyfile_filter_missing() {
+        setup_temp(fake_under_data_end, |temp_path, under_dir| {
+            let keyfile = temp_path.join("keyfile");
+
+            match keyfile_filter(under_dir, &keyfile, &fake_targets())
+                .map_err(filter_error(None))
+            {
+                Ok(_) => panic!("found dirs without a keyfile"),
+                Err(ManageError::Filter {
+                    path: Some(path),
+                    message,
+                }) => {
+                    assert_eq!(path, keyfile);
+                    assert_eq!(message, "keyfile missing");
+                }
+                Err(err) => panic!("not a filter error {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_keyfile_filter_unknown_target() {
+        setup_temp(fake_under_data_end, |temp_path, under_dir| {
+            let keyfile = temp_path.join("keyfile");
+
+            if let Err(err) = fs::write(&keyfile, "Nothing") {
+                panic!("{}", err);
+            }
+
+            match keyfile_filter(under_dir, &keyfile, &fake_targets()) {
+                Ok(_) => panic!("found dirs for unknown target"),
+                Err(err) => assert!(
+                    format!("{}", err)
+                        .ends_with("no target named Nothing in config"),
+                    "{}",
+                    err
+                ),
+            }
+        })
+    }
+
+    #[test]
+    fn test_get_dirs_negated_and_overlapping_pat() {
+        setup_temp(fake_under_data_end, |temp_path, under_dir| {
+            let read_under = match fs::read_dir(under_dir) {
+                Ok(val) => val,
+                Err(err) => panic!("{}", err),
+            };
+
+            let hand_made_data: Vec<PathBuf> = [
+                "under_dir/fake_i3_all",
+                "under_dir/fake_polybar_M",
+                "under_dir/fake_polybar_O",
+                "under_dir/fake_termite_M",
+            ]
+            .iter()
+            .map(|dir| temp_path.join(dir))
+            .collect();
+
+            let patterns: Vec<String> =
+                ["*_all", "*_{all,M}", "fake_polybar_[OM]", "!*zsh*"]
+                    .iter()
+                    .map(|pat| pat.to_string())
+                    .collect();
+
+            match get_dirs(read_under, &patterns, &[]) {
+                Ok(found_vec) => assert_eq!(found_vec, hand_made_data),
+                Err(err) => panic!("get_dirs failed {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_get_dirs_bad_pat() {
+        setup_temp(fake_under_data_end, |_, under_dir| {
+            let read_under = match fs::read_dir(under_dir) {
+                Ok(val) => val,
+                Err(err) => panic!("{}", err),
+            };
+
+            if get_dirs(read_under, &["*_[all".to_string()], &[]).is_ok() {
+                panic!("get_dirs took a bad pattern");
+            }
+        })
+    }
+
+    #[test]
+    fn test_get_dirs_regex() {
+        setup_temp(fake_under_data_end, |temp_path, under_dir| {
+            let read_under = match fs::read_dir(under_dir) {
+                Ok(val) => val,
+                Err(err) => panic!("{}", err),
+            };
+
+            let hand_made_data: Vec<PathBuf> = [
+                "under_dir/fake_polybar_M",
+                "under_dir/fake_polybar_O",
+                "under_dir/fake_zsh_all",
+            ]
+            .iter()
+            .map(|dir| temp_path.join(dir))
+            .collect();
+
+            let file_regex = [Regex::new("^fake_(polybar)_(M|O)$").unwrap()];
+
+            match get_dirs(read_under, &["*zsh*".to_string()], &file_regex) {
+                Ok(found_vec) => assert_eq!(found_vec, hand_made_data),
+                Err(err) => panic!("get_dirs failed {}", err),
             }
         })
     }
@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+
+use crate::error::Error as ManageError;
+
+type WorkResult = Result<bool, Box<dyn Error>>;
+
+// each run backs up in to its own dir named after the time it started, the
+// nanoseconds keep two runs in the same second apart
+pub fn make_backup_dir(backup_root: &Path) -> PathBuf {
+    let (secs, nanos) = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| (time.as_secs(), time.subsec_nanos()))
+        .unwrap_or((0, 0));
+
+    backup_root.join(format!("{}.{:09}", secs, nanos))
+}
+
+// when a backup set was made from its name, sets named before the
+// nanoseconds were added have only the seconds
+fn backup_time(backup_dir: &Path) -> Option<(u64, u32)> {
+    let name = backup_dir.file_name()?.to_str()?;
+
+    match name.split_once('.') {
+        Some((secs, nanos)) => Some((secs.parse().ok()?, nanos.parse().ok()?)),
+        None => Some((name.parse().ok()?, 0)),
+    }
+}
+
+// fall back to copying when the backup dir is on an other file system
+pub fn move_path(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_err(ManageError::io_at("cant make dir", parent))?;
+    }
+
+    let doing = format!("cant move to {:?}", to);
+
+    let err = match fs::rename(from, to) {
+        Ok(_) => return Ok(()),
+        Err(err) => err,
+    };
+
+    if from.is_dir() {
+        return Err(Box::from(ManageError::io_at(&doing, from)(err)));
+    }
+
+    fs::copy(from, to).map_err(ManageError::io_at(&doing, from))?;
+    fs::remove_file(from).map_err(ManageError::io_at("cant remove", from))?;
+
+    Ok(())
+}
+
+// move a file in upper_dir to the same relative path in backup_dir
+pub fn backup_path(
+    upper_dir: &Path,
+    backup_dir: &Path,
+    to_backup: &Path,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let relative = to_backup
+        .strip_prefix(upper_dir)
+        .map_err(|_| format!("{:?} is not in upper dir", to_backup))?;
+
+    let backup_file = backup_dir.join(relative);
+
+    move_path(to_backup, &backup_file)?;
+
+    Ok(backup_file)
+}
+
+// get every file in a backup set, dirs are walked in to
+fn backup_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut to_ret: Vec<PathBuf> = vec![];
+
+    let read_err = || ManageError::io_at("cant read backup", dir);
+
+    for entry in fs::read_dir(dir).map_err(read_err())? {
+        let entry_path = entry.map_err(read_err())?.path();
+        let file_type = entry_path
+            .symlink_metadata()
+            .map_err(ManageError::io_at("cant read backup", &entry_path))?
+            .file_type();
+
+        if file_type.is_dir() {
+            to_ret.append(&mut backup_files(&entry_path)?);
+        } else {
+            to_ret.push(entry_path);
+        }
+    }
+
+    Ok(to_ret)
+}
+
+// remove the dirs left empty once there files are restored
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(read_dir) = fs::read_dir(dir) {
+        for entry in read_dir.flatten() {
+            if entry.path().is_dir() {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+
+    let _ = fs::remove_dir(dir);
+}
+
+// put backed up files back in upper_dir, the newest backup of a path wins and
+// paths that exist again are left in the backup
+pub fn restore_backups(
+    upper_dir: &Path,
+    backup_root: &Path,
+    dry_run: bool,
+) -> WorkResult {
+    if !backup_root.is_dir() {
+        return Ok(false);
+    }
+
+    let mut backup_sets: Vec<((u64, u32), PathBuf)> = fs::read_dir(backup_root)
+        .map_err(ManageError::io_at("cant read backups", backup_root))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| Some((backup_time(&path)?, path)))
+        .collect();
+
+    backup_sets.sort();
+    backup_sets.reverse();
+
+    let mut did_work = false;
+    for (_, backup_dir) in &backup_sets {
+        for backup_file in backup_files(backup_dir)? {
+            let relative = backup_file.strip_prefix(backup_dir)?;
+            let upper_file = upper_dir.join(relative);
+
+            if upper_file.symlink_metadata().is_ok() {
+                warn!("cant restore {:?} it exists", upper_file);
+                continue;
+            }
+
+            if dry_run {
+                info!("would restore {:?}", upper_file);
+            } else {
+                move_path(&backup_file, &upper_file)?;
+            }
+
+            did_work = true;
+        }
+
+        if !dry_run {
+            remove_empty_dirs(backup_dir);
+        }
+    }
+
+    Ok(did_work)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_backup_then_restore() {
+        let temp_path = tempdir().unwrap().into_path();
+        let upper_dir = temp_path.join("upper");
+        let backup_root = temp_path.join("backup");
+        let backup_dir = backup_root.join("100");
+
+        let zshrc = upper_dir.join(".zshrc");
+        let i3_conf = upper_dir.join(".config/i3/config");
+
+        fs::create_dir_all(i3_conf.parent().unwrap()).unwrap();
+        fs::write(&zshrc, "zsh").unwrap();
+        fs::write(&i3_conf, "i3").unwrap();
+
+        for to_backup in &[&zshrc, &i3_conf] {
+            if let Err(err) = backup_path(&upper_dir, &backup_dir, to_backup) {
+                panic!("cant backup {}", err);
+            }
+
+            assert!(!to_backup.exists(), "file not moved");
+        }
+
+        assert!(backup_dir.join(".config/i3/config").is_file());
+
+        match restore_backups(&upper_dir, &backup_root, false) {
+            Ok(did_work) => assert!(did_work, "nothing restored"),
+            Err(err) => panic!("cant restore {}", err),
+        }
+
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "zsh");
+        assert_eq!(fs::read_to_string(&i3_conf).unwrap(), "i3");
+        assert!(!backup_dir.exists(), "backup dir left behind");
+
+        // a file that is gone is an io error about that file
+        let missing = upper_dir.join(".vimrc");
+        match backup_path(&upper_dir, &backup_dir, &missing) {
+            Ok(_) => panic!("backed up a missing file"),
+            Err(err) => match ManageError::from(err) {
+                ManageError::Io {
+                    path: Some(path), ..
+                } => assert_eq!(path, missing),
+                err => panic!("not an io error {}", err),
+            },
+        }
+    }
+
+    #[test]
+    fn test_restore_newest_backup() {
+        let temp_path = tempdir().unwrap().into_path();
+        let upper_dir = temp_path.join("upper");
+        let backup_root = temp_path.join("backup");
+
+        // two runs in the same second after one named by seconds only
+        for (set, zshrc) in &[
+            ("100", "oldest"),
+            ("200.000000005", "old"),
+            ("200.000000010", "new"),
+        ] {
+            fs::create_dir_all(backup_root.join(set)).unwrap();
+            fs::write(backup_root.join(set).join(".zshrc"), zshrc).unwrap();
+        }
+        fs::create_dir_all(&upper_dir).unwrap();
+
+        if let Err(err) = restore_backups(&upper_dir, &backup_root, false) {
+            panic!("cant restore {}", err);
+        }
+
+        assert_eq!(
+            fs::read_to_string(upper_dir.join(".zshrc")).unwrap(),
+            "new"
+        );
+        assert!(backup_root.join("100/.zshrc").exists(), "old backup lost");
+        assert!(
+            backup_root.join("200.000000005/.zshrc").exists(),
+            "old backup lost"
+        );
+
+        assert_ne!(
+            make_backup_dir(&backup_root),
+            make_backup_dir(&backup_root),
+            "two runs share a backup dir"
+        );
+    }
+}
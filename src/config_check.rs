@@ -0,0 +1,684 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+use serde::de::DeserializeOwned;
+use toml::{value::Table, Value};
+
+use crate::auto_filters::{get_dirs, FilterType};
+use crate::hook_utils::HookConf;
+use crate::my_utils::get_linux_hostname;
+use crate::secret_utils::{default_key_file, SecretKey};
+use crate::user_config::{
+    base_dir, check_old_target, from_base, invalid_destinations, is_section,
+    BaseVars, Conf, Deploy, FilterRules, Invalid, Target, TargetConf,
+};
+
+// one thing wrong with the config, line and col start at 1
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+// finds where tables and keys are in the config text, toml::Value forgets
+// where things came from
+struct Locator<'a> {
+    src: &'a str,
+    headers: Vec<(Vec<String>, usize)>,
+    keys: Vec<(Vec<String>, String, usize)>,
+}
+
+fn split_key_path(path: &str) -> Vec<String> {
+    path.split('.')
+        .map(|part| {
+            part.trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string()
+        })
+        .collect()
+}
+
+impl<'a> Locator<'a> {
+    fn new(src: &'a str) -> Self {
+        let mut headers: Vec<(Vec<String>, usize)> = vec![];
+        let mut keys: Vec<(Vec<String>, String, usize)> = vec![];
+
+        let mut table: Vec<String> = vec![];
+        let mut offset = 0;
+
+        for line in src.split('\n') {
+            let trimmed = line.trim_start();
+            let indent = line.len() - trimmed.len();
+
+            if trimmed.starts_with('[') {
+                if let Some(close) = trimmed.find(']') {
+                    let name = trimmed[1..close].trim_matches('[');
+                    table = split_key_path(name);
+                    headers.push((table.clone(), offset + indent));
+                }
+            } else if let Some(equals) = trimmed.find('=') {
+                if !trimmed.starts_with('#') {
+                    let key = split_key_path(&trimmed[..equals]).join(".");
+                    keys.push((table.clone(), key, offset + indent));
+                }
+            }
+
+            offset += line.len() + 1;
+        }
+
+        Locator { src, headers, keys }
+    }
+
+    fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.src[..offset.min(self.src.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+        (line, col)
+    }
+
+    fn table_offset(&self, table: &[&str]) -> usize {
+        self.headers
+            .iter()
+            .find(|(path, _)| path.iter().eq(table.iter()))
+            .map_or(0, |(_, offset)| *offset)
+    }
+
+    fn key_offset(&self, table: &[&str], key: &str) -> usize {
+        self.keys
+            .iter()
+            .find(|(path, name, _)| path.iter().eq(table.iter()) && name == key)
+            .map_or_else(|| self.table_offset(table), |(_, _, offset)| *offset)
+    }
+
+    // the quoted item in an array after its key
+    fn item_offset(&self, table: &[&str], key: &str, item: &str) -> usize {
+        let key_offset = self.key_offset(table, key);
+
+        ["'", "\""]
+            .iter()
+            .filter_map(|quote| {
+                self.src[key_offset..]
+                    .find(&format!("{}{}{}", quote, item, quote))
+            })
+            .min()
+            .map_or(key_offset, |found| key_offset + found)
+    }
+}
+
+struct Checker<'a> {
+    loc: Locator<'a>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn add(&mut self, offset: usize, message: String) {
+        let (line, col) = self.loc.line_col(offset);
+        self.problems.push(Problem { line, col, message });
+    }
+
+    fn at_table(&mut self, table: &[&str], message: String) {
+        let offset = self.loc.table_offset(table);
+        self.add(offset, message);
+    }
+
+    fn at_key(&mut self, table: &[&str], key: &str, message: String) {
+        let offset = self.loc.key_offset(table, key);
+        self.add(offset, message);
+    }
+
+    // put what the checks shared with Conf::new found at its key or item
+    fn invalid(&mut self, table: &[&str], found: Vec<Invalid>) {
+        for invalid in found {
+            let offset = match &invalid.item {
+                Some(item) => self.loc.item_offset(table, &invalid.key, item),
+                None => self.loc.key_offset(table, &invalid.key),
+            };
+
+            self.add(offset, invalid.message);
+        }
+    }
+
+    // read the table at path in to T a key at a time so serde's error for a
+    // bad key is put at that key, the config structs default every field
+    // so one key reads on its own. bad keys are left out of what is given
+    // back, none if it is not a table at all
+    fn section<T: DeserializeOwned>(
+        &mut self,
+        path: &[&str],
+        value: &Value,
+    ) -> Option<T> {
+        let table = match value.as_table() {
+            Some(table) => table,
+            None => {
+                if let Err(err) = value.clone().try_into::<T>() {
+                    let (key, parent) = path.split_last()?;
+                    self.at_key(parent, key, err.to_string());
+                }
+
+                return None;
+            }
+        };
+
+        let mut good = Table::new();
+        for (key, key_val) in table {
+            let mut alone = Table::new();
+            alone.insert(key.clone(), key_val.clone());
+
+            match Value::Table(alone).try_into::<T>() {
+                Ok(_) => {
+                    good.insert(key.clone(), key_val.clone());
+                }
+                Err(err) => self.at_key(path, key, err.to_string()),
+            }
+        }
+
+        Value::Table(good).try_into().ok()
+    }
+
+    // check base_vars and give back the under dir if it can be found
+    fn base_vars(&mut self, toml_val: &Value) -> Option<PathBuf> {
+        let table = ["base_vars"];
+
+        let base_vars = match toml_val.get("base_vars") {
+            Some(base_vars) => base_vars,
+            None => {
+                self.add(0, "need base_vars".to_string());
+                return None;
+            }
+        };
+
+        if base_vars.is_table() && base_vars.get("under_dir").is_none() {
+            self.at_table(&table, "need under_dir".to_string());
+        }
+
+        let base_vars: BaseVars = self.section(&table, base_vars)?;
+
+        let base = base_dir(base_vars.base.as_deref()).ok()?;
+        let upper = base_vars.upper_dir.as_deref().unwrap_or(&base);
+
+        Some(Path::new(upper).join(base_vars.under_dir?))
+    }
+
+    // check filter_rules and give back the filter type, none if it is bad,
+    // and the keyfile
+    fn filter_rules(
+        &mut self,
+        toml_val: &Value,
+    ) -> (Option<FilterType>, PathBuf) {
+        let mut rules = FilterRules::default();
+        let mut all_good = true;
+
+        if let Some(filter_rules) = toml_val.get("filter_rules") {
+            let before = self.problems.len();
+
+            rules = self
+                .section(&["filter_rules"], filter_rules)
+                .unwrap_or_default();
+            all_good = self.problems.len() == before;
+        }
+
+        let base = toml_val
+            .get("base_vars")
+            .and_then(|base_vars| base_vars.get("base"))
+            .and_then(Value::as_str);
+        let base = base_dir(base).unwrap_or_default();
+
+        let filter_type = Some(rules.filter_type).filter(|_| all_good);
+
+        (filter_type, from_base(&base, &rules.keyfile))
+    }
+
+    fn deploy(&mut self, toml_val: &Value) {
+        let table = ["deploy"];
+
+        let deploy: Deploy = match toml_val
+            .get("deploy")
+            .and_then(|deploy| self.section(&table, deploy))
+        {
+            Some(deploy) => deploy,
+            None => return,
+        };
+
+        self.invalid(&table, deploy.invalid());
+    }
+
+    fn hooks(&mut self, toml_val: &Value, under_dir: Option<&PathBuf>) {
+        let hooks: BTreeMap<String, Value> = match toml_val
+            .get("hooks")
+            .and_then(|hooks| self.section(&["hooks"], hooks))
+        {
+            Some(hooks) => hooks,
+            None => return,
+        };
+
+        for (package, package_hooks) in &hooks {
+            let table = ["hooks", package.as_str()];
+
+            self.section::<HookConf>(&table, package_hooks);
+
+            if let Some(under_dir) = under_dir {
+                if !under_dir.join(package).is_dir() {
+                    self.at_table(
+                        &table,
+                        format!("hooks for missing package {}", package),
+                    );
+                }
+            }
+        }
+    }
+
+    fn destinations(&mut self, toml_val: &Value, under_dir: Option<&PathBuf>) {
+        let table = ["destinations"];
+
+        let destinations: BTreeMap<String, String> = match toml_val
+            .get("destinations")
+            .and_then(|destinations| self.section(&table, destinations))
+        {
+            Some(destinations) => destinations,
+            None => return,
+        };
+
+        self.invalid(&table, invalid_destinations(&destinations));
+
+        if let Some(under_dir) = under_dir {
+            for package in destinations.keys() {
+                if !under_dir.join(package).is_dir() {
+                    self.at_key(
+                        &table,
+                        package,
+                        format!("destination for missing package {}", package),
+                    );
+                }
+            }
+        }
+    }
+
+    // every target with the table path it was found at
+    fn find_targets(&mut self, toml_val: &Value) -> Vec<(Vec<String>, Value)> {
+        let mut to_ret: Vec<(Vec<String>, Value)> = vec![];
+
+        let top_table = match toml_val.as_table() {
+            Some(top_table) => top_table,
+            None => return to_ret,
+        };
+
+        for (key, value) in top_table {
+            match key.as_str() {
+                "targets" => {
+                    let targets: BTreeMap<String, Value> =
+                        self.section(&["targets"], value).unwrap_or_default();
+
+                    for (name, target) in targets {
+                        to_ret
+                            .push((vec!["targets".to_string(), name], target));
+                    }
+                }
+                _ if is_section(key) => {}
+                _ => match check_old_target(key, value) {
+                    Ok(()) => to_ret.push((vec![key.clone()], value.clone())),
+                    Err(message) if value.is_table() => {
+                        self.at_table(&[key], message)
+                    }
+                    Err(message) => self.at_key(&[], key, message),
+                },
+            }
+        }
+
+        to_ret
+    }
+
+    fn target(
+        &mut self,
+        path: &[String],
+        target: &Value,
+        under_dir: Option<&PathBuf>,
+    ) {
+        let table: Vec<&str> = path.iter().map(String::as_str).collect();
+        let name = table[table.len() - 1];
+
+        let before = self.problems.len();
+
+        let target_conf: TargetConf = match self.section(&table, target) {
+            Some(target_conf) => target_conf,
+            None => return,
+        };
+
+        self.invalid(&table, target_conf.invalid(name));
+
+        // what it selects cant be known with a bad key
+        if self.problems.len() != before {
+            return;
+        }
+
+        if target_conf.file_patterns.is_empty()
+            && target_conf.file_regex.is_empty()
+        {
+            self.at_table(&table, format!("target {} selects nothing", name));
+            return;
+        }
+
+        let read_under = match under_dir.map(fs::read_dir) {
+            Some(Ok(read_under)) => read_under,
+            _ => return,
+        };
+
+        let target = match Target::new(name.to_string(), target_conf) {
+            Ok(target) => target,
+            Err(_) => return,
+        };
+
+        if get_dirs(read_under, &target.file_patterns, &target.file_regex)
+            .is_err()
+        {
+            self.at_table(
+                &table,
+                format!("target {} matches no package dirs", name),
+            );
+        }
+    }
+
+    // make sure this machine can pick a target
+    fn machine_target(
+        &mut self,
+        filter_type: &FilterType,
+        keyfile: &Path,
+        names: &[String],
+    ) {
+        let table = ["filter_rules"];
+
+        if let FilterType::Hostname = filter_type {
+            let hostname = get_linux_hostname().unwrap_or_default();
+
+            if !names.contains(&hostname) {
+                self.at_key(
+                    &table,
+                    "filter_type",
+                    format!("no target for hostname {}", hostname),
+                );
+            }
+        } else {
+            let key_name = match fs::read_to_string(keyfile) {
+                Ok(key_string) => key_string.trim().to_string(),
+                Err(_) => {
+                    self.at_key(
+                        &table,
+                        "keyfile",
+                        format!("keyfile missing {:?}", keyfile),
+                    );
+                    return;
+                }
+            };
+
+            if !names.contains(&key_name) {
+                self.at_key(
+                    &table,
+                    "keyfile",
+                    format!("no target named {:?} from keyfile", key_name),
+                );
+            }
+        }
+    }
+}
+
+// check the whole config and report every problem found
+pub fn check_config_str(toml_string: &str) -> Vec<Problem> {
+    let mut checker = Checker {
+        loc: Locator::new(toml_string),
+        problems: vec![],
+    };
+
+    let toml_val: Value = match toml_string.parse::<Value>() {
+        Ok(toml_val) => toml_val,
+        Err(err) => {
+            let (line, col) = err.line_col().unwrap_or((0, 0));
+
+            return vec![Problem {
+                line: line + 1,
+                col: col + 1,
+                message: format!("bad toml {}", err),
+            }];
+        }
+    };
+
+    let under_dir = checker.base_vars(&toml_val);
+    let (filter_type, keyfile) = checker.filter_rules(&toml_val);
+
+    checker.deploy(&toml_val);
+    checker.hooks(&toml_val, under_dir.as_ref());
+    checker.destinations(&toml_val, under_dir.as_ref());
+
+    let targets = checker.find_targets(&toml_val);
+
+    if targets.is_empty() {
+        checker.add(0, "no filters given".to_string());
+    }
+
+    let mut names: Vec<String> = vec![];
+    for (path, target) in &targets {
+        checker.target(path, target, under_dir.as_ref());
+
+        let name = path[path.len() - 1].clone();
+        if names.contains(&name) {
+            checker.at_table(
+                &path.iter().map(String::as_str).collect::<Vec<&str>>(),
+                format!("target {} given twice", name),
+            );
+        }
+
+        names.push(name);
+    }
+
+    // a bad filter_type was already reported
+    if let Some(filter_type) = filter_type {
+        checker.machine_target(&filter_type, &keyfile, &names);
+    }
+
+    checker
+        .problems
+        .sort_by_key(|problem| (problem.line, problem.col));
+
+    checker.problems
+}
+
+pub fn check_config(
+    config_path: &Path,
+) -> Result<Vec<Problem>, Box<dyn Error>> {
+    let toml_string = fs::read_to_string(config_path)
+        .map_err(|err| format!("cant read config {:?} {}", config_path, err))?;
+
+    Ok(check_config_str(&toml_string))
+}
+
+// check what a working config points to, the under dir and the keys
+pub fn check_setup(conf: &Conf) -> Vec<String> {
+    let mut problems: Vec<String> = vec![];
+
+    let under_dir = Path::new(&conf.upper_dir).join(&conf.under_dir);
+
+    if !under_dir.is_dir() {
+        problems.push(format!("under dir {:?} is missing", under_dir));
+    } else if let Err(err) = Repository::open(&under_dir) {
+        problems
+            .push(format!("under dir {:?} is not a repo {}", under_dir, err));
+    }
+
+    let key_file = match &conf.secret_key {
+        Some(key_file) => Ok(key_file.clone()),
+        None => default_key_file(),
+    };
+
+    // no key is fine until the first secret is made
+    match key_file {
+        Ok(key_file) if key_file.exists() => {
+            if let Err(err) = SecretKey::load(&key_file) {
+                problems.push(err.to_string());
+            }
+        }
+        Ok(_) => {}
+        Err(err) => problems.push(err),
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::user_config::make_config;
+
+    fn messages(problems: &[Problem]) -> Vec<String> {
+        problems.iter().map(|prob| format!("{}", prob)).collect()
+    }
+
+    #[test]
+    fn test_check_config_reports_everything() {
+        let fake_config = "[base_vars]
+base = '/nowhere'
+
+[filter_rules]
+filter_type = 'hostnme'
+
+[targets.Monolith]
+file_patterns = ['*_all', '*_[M']
+file_regx = ['^zsh$']
+
+[hooks.fonts_all]
+post_lnk = 'fc-cache -f'
+pre_link = 1
+
+[destinations]
+nvim_all = '@conf/nvim'
+";
+
+        let problems = messages(&check_config_str(fake_config));
+
+        assert_eq!(
+            problems,
+            vec![
+                "1:1: need under_dir",
+                "5:1: unknown variant `hostnme`, expected `keyfile` or \
+                 `hostname` for key `filter_type`",
+                "8:27: bad file_patterns in Monolith: unclosed [ in pattern *_[M",
+                "9:1: unknown field `file_regx`, expected one of \
+                 `file_patterns`, `file_regex`, `vars`",
+                "12:1: unknown field `post_lnk`, expected one of `pre_link`, \
+                 `post_link`, `pre_unlink`, `post_unlink`",
+                "13:1: invalid type: integer `1`, expected a string for key \
+                 `pre_link`",
+                "16:1: unknown root @conf for nvim_all, expected one of \
+                 @config, @data, @cache, @state, @bin",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_section() {
+        assert!(is_section("deploy"));
+        assert!(!is_section("targest"));
+    }
+
+    #[test]
+    fn test_check_config_bad_toml() {
+        let problems = check_config_str("[base_vars]\nunder_dir = \n");
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, 2, "{}", problems[0]);
+    }
+
+    #[test]
+    fn test_check_config_targets_and_keyfile() {
+        let temp_path = tempdir().unwrap().into_path();
+
+        fs::create_dir_all(temp_path.join(".dots/zsh_all")).unwrap();
+        fs::write(temp_path.join(".manage_keyfile"), "Odimm\n").unwrap();
+
+        let fake_config = format!(
+            "[base_vars]
+base = '{}'
+under_dir = '.dots'
+
+[Monolith]
+file_patterns = ['*_all']
+
+[Odimm]
+file_patterns = ['*_O']
+",
+            temp_path.display()
+        );
+
+        let problems = messages(&check_config_str(&fake_config));
+
+        assert_eq!(problems, vec!["8:1: target Odimm matches no package dirs"]);
+
+        fs::write(temp_path.join(".manage_keyfile"), "Laptop\n").unwrap();
+
+        let problems = messages(&check_config_str(&fake_config));
+
+        assert_eq!(
+            problems,
+            vec![
+                "1:1: no target named \"Laptop\" from keyfile",
+                "8:1: target Odimm matches no package dirs",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_setup() {
+        let temp_path = tempdir().unwrap().into_path();
+        let config_path = temp_path.join("config.toml");
+        let key_file = temp_path.join("secret.key");
+
+        fs::create_dir_all(temp_path.join(".dots/zsh_all")).unwrap();
+        fs::write(&key_file, "00".repeat(32)).unwrap();
+        fs::set_permissions(&key_file, fs::Permissions::from_mode(0o644))
+            .unwrap();
+
+        fs::write(
+            &config_path,
+            format!(
+                "[base_vars]
+base = '{}'
+under_dir = '.dots'
+
+[deploy]
+secret_key = 'secret.key'
+
+[Monolith]
+file_patterns = ['*_all']
+",
+                temp_path.display()
+            ),
+        )
+        .unwrap();
+
+        let conf = make_config(&config_path).unwrap();
+        let problems = check_setup(&conf);
+
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("is not a repo"), "{}", problems[0]);
+        assert!(problems[1].contains("can be read by others"));
+
+        Repository::init(temp_path.join(".dots")).unwrap();
+        fs::set_permissions(&key_file, fs::Permissions::from_mode(0o600))
+            .unwrap();
+
+        assert!(check_setup(&conf).is_empty());
+    }
+}
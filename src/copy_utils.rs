@@ -0,0 +1,116 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Error as ManageError;
+use crate::glob_utils::glob_match;
+
+// which files in under_dir are copied in to upper_dir instead of linked, for
+// programs that replace links on save or wont read them
+#[derive(Default, Clone, Debug)]
+pub struct CopyRule {
+    pub under_dir: PathBuf,
+    // globs matched against the path in under_dir, a pattern matching a dir
+    // copies everything in it
+    pub patterns: Vec<String>,
+}
+
+impl CopyRule {
+    pub fn new(under_dir: &Path, patterns: &[String]) -> Self {
+        CopyRule {
+            under_dir: under_dir.to_owned(),
+            patterns: patterns.to_vec(),
+        }
+    }
+
+    pub fn is_copy(&self, target_path: &Path) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+
+        let relative = match target_path.strip_prefix(&self.under_dir) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        // firefox_all, then firefox_all/.mozilla and so on
+        let mut prefix = PathBuf::new();
+        for component in relative.components() {
+            prefix.push(component);
+
+            let prefix_str = prefix.to_string_lossy();
+            let found = self.patterns.iter().any(|pattern| {
+                glob_match(pattern, &prefix_str).unwrap_or(false)
+            });
+
+            if found {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+pub fn checksum(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn file_checksum(path: &Path) -> Result<String, Box<dyn Error>> {
+    let content =
+        fs::read(path).map_err(ManageError::io_at("cant read", path))?;
+
+    Ok(checksum(&content))
+}
+
+// the upper file is a real file with the same bytes as the target
+pub fn is_deployed(upper_file: &Path, target_file: &Path) -> bool {
+    let is_file = upper_file
+        .symlink_metadata()
+        .map(|meta| meta.file_type().is_file())
+        .unwrap_or(false);
+
+    is_file
+        && match (fs::read(upper_file), fs::read(target_file)) {
+            (Ok(upper), Ok(target)) => upper == target,
+            _ => false,
+        }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_copy_rule() {
+        let rule = CopyRule::new(
+            Path::new("/under"),
+            &[
+                "firefox_*".to_string(),
+                "code_all/.config/Code/User/settings.json".to_string(),
+            ],
+        );
+
+        assert!(rule.is_copy(Path::new("/under/firefox_all/.mozilla/prefs")));
+        assert!(rule.is_copy(Path::new("/under/firefox_M")));
+        assert!(rule.is_copy(Path::new(
+            "/under/code_all/.config/Code/User/settings.json"
+        )));
+        assert!(!rule.is_copy(Path::new("/under/code_all/.config/Code/x")));
+        assert!(!rule.is_copy(Path::new("/other/firefox_all/prefs")));
+        assert!(!CopyRule::default().is_copy(Path::new("/under/firefox_all")));
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(
+            checksum(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
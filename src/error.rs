@@ -0,0 +1,272 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// what went wrong in a way a wrapper can act on, each kind has its own exit
+// code and holds the paths it is about
+#[derive(Debug)]
+pub enum Error {
+    // the config can not be read or does not make sense
+    Config {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    // no target or package dirs could be picked for this machine, the path
+    // is the keyfile or under_dir
+    Filter {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    // a real file is where a link or copy should go
+    Conflict {
+        path: PathBuf,
+        target: PathBuf,
+    },
+    // a file in upper_dir manage did not make or that changed since it was
+    // deployed
+    NotOwned {
+        path: PathBuf,
+        message: String,
+    },
+    // cloning, pulling or pushing under_dir
+    Git {
+        path: Option<PathBuf>,
+        message: String,
+    },
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    // status found paths that are not linked, the report says which
+    NotLinked {
+        count: usize,
+    },
+    // how undoing the changes made before the error went
+    RolledBack {
+        source: Box<Error>,
+        message: String,
+    },
+    Other(String),
+}
+
+impl Error {
+    // the code main exits with, 1 is left for errors with no kind
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Other(_) => 1,
+            Error::Config { .. } => 2,
+            Error::Filter { .. } => 3,
+            Error::Conflict { .. } => 4,
+            Error::NotOwned { .. } => 5,
+            Error::Git { .. } => 6,
+            Error::Io { .. } => 7,
+            Error::NotLinked { .. } => 8,
+            Error::RolledBack { source, .. } => source.exit_code(),
+        }
+    }
+
+    // for map_err at an fs call, keeps the path and what was being done
+    pub fn io_at<'a>(
+        doing: &'a str,
+        path: &'a Path,
+    ) -> impl FnOnce(io::Error) -> Self + 'a {
+        move |err| Error::Io {
+            path: Some(path.to_owned()),
+            source: io::Error::new(err.kind(), format!("{}: {}", doing, err)),
+        }
+    }
+
+    // errors that are only a message become the given kind, errors that
+    // already know their kind keep it
+    pub fn or_kind<F>(err: Box<dyn StdError>, kind: F) -> Self
+    where
+        F: FnOnce(String) -> Self,
+    {
+        match Error::from(err) {
+            Error::Other(message) => kind(message),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config {
+                path: Some(path),
+                message,
+            } => write!(f, "config {:?}: {}", path, message),
+            Error::Config {
+                path: None,
+                message,
+            }
+            | Error::Filter {
+                path: None,
+                message,
+            }
+            | Error::NotOwned { message, .. }
+            | Error::Git {
+                path: None,
+                message,
+            }
+            | Error::Other(message) => write!(f, "{}", message),
+            Error::Filter {
+                path: Some(path),
+                message,
+            } => write!(f, "{:?}: {}", path, message),
+            Error::Conflict { path, .. } => {
+                write!(f, "file exists {:?}", path)
+            }
+            Error::Git {
+                path: Some(path),
+                message,
+            } => write!(f, "repo {:?}: {}", path, message),
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{:?}: {}", path, source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::NotLinked { count } => {
+                write!(f, "{} path[s] not linked", count)
+            }
+            Error::RolledBack { source, message } => {
+                write!(f, "{}, {}", source, message)
+            }
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::RolledBack { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io {
+            path: None,
+            source: err,
+        }
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(err: git2::Error) -> Self {
+        Error::Git {
+            path: None,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Config {
+            path: None,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_string())
+    }
+}
+
+// the modules still build their errors as boxes, keep the kind when the box
+// holds one
+impl From<Box<dyn StdError>> for Error {
+    fn from(err: Box<dyn StdError>) -> Self {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return Error::from(*err),
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<git2::Error>() {
+            Ok(err) => return Error::from(*err),
+            Err(err) => err,
+        };
+
+        match err.downcast::<toml::de::Error>() {
+            Ok(err) => Error::from(*err),
+            Err(err) => Error::Other(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_error_kinds() {
+        let conflict: Box<dyn StdError> = Box::from(Error::Conflict {
+            path: Path::new("/home/me/.zshrc").to_owned(),
+            target: Path::new("/home/me/.dots/zsh_all/.zshrc").to_owned(),
+        });
+
+        // a kind survives a trip through a box
+        let conflict = Error::from(conflict);
+        assert!(matches!(conflict, Error::Conflict { .. }));
+        assert_eq!(conflict.exit_code(), 4);
+        assert_eq!(format!("{}", conflict), "file exists \"/home/me/.zshrc\"");
+
+        let rolled = Error::RolledBack {
+            source: Box::new(conflict),
+            message: "rolled back 2 change[s]".to_string(),
+        };
+        assert_eq!(rolled.exit_code(), 4);
+
+        let io_err: Box<dyn StdError> =
+            Box::from(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(Error::from(io_err).exit_code(), 7);
+
+        let io_at = Error::io_at(
+            "cant make symlink",
+            Path::new("/home/me/.zshrc"),
+        )(io::Error::from(io::ErrorKind::AlreadyExists));
+        assert_eq!(io_at.exit_code(), 7);
+        assert_eq!(
+            format!("{}", io_at),
+            "\"/home/me/.zshrc\": cant make symlink: entity already exists"
+        );
+
+        let filter =
+            Error::or_kind(Box::from("no targets"), |message| Error::Filter {
+                path: Some(Path::new("/home/me/.manage_keyfile").to_owned()),
+                message,
+            });
+        assert_eq!(filter.exit_code(), 3);
+
+        let git = Error::or_kind(
+            Box::from(git2::Error::from_str("has diverged")),
+            |message| Error::Filter {
+                path: None,
+                message,
+            },
+        );
+        assert!(matches!(git, Error::Git { .. }));
+
+        assert_eq!(Error::from("bad action").exit_code(), 1);
+    }
+}
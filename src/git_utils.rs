@@ -1,8 +1,18 @@
-use git2::{self, Repository};
+use git2::{
+    self,
+    build::{CheckoutBuilder, RepoBuilder},
+    Cred, ErrorClass, ErrorCode, FetchOptions, IndexAddOption, PushOptions,
+    RemoteCallbacks, Repository, Signature, StatusOptions,
+};
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::io;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 
-use crate::MainApp;
+use log::{debug, info, warn};
+
+use crate::{error::Error as ManageError, Action, MainApp};
 
 fn initial_clone(main_app: &MainApp) -> Result<Repository, Box<dyn Error>> {
     let git_url: String = if main_app.git_url.is_none() {
@@ -11,31 +21,657 @@ fn initial_clone(main_app: &MainApp) -> Result<Repository, Box<dyn Error>> {
         main_app.git_url.clone().unwrap()
     };
 
-    println!("would you like to clone the given url");
-    println!("{}", git_url);
+    // only ask when some one can answer
+    if !io::stdin().is_terminal() {
+        return Err(Box::from(format!(
+            "no repo in {:?}, run manage init to clone one",
+            main_app.under_dir
+        )));
+    }
+
+    // a question and not a log line, on stderr so -q and json runs still
+    // show it without it ending up in the output
+    eprintln!("would you like to clone the given url");
+    eprintln!("{}", git_url);
 
     let mut user_out = String::new();
     io::stdin()
         .read_line(&mut user_out)
-        .expect("couldn't get line");
+        .map_err(|err| format!("cant read an answer {}", err))?;
 
     if user_out.trim().to_lowercase() == "y" {
-        Repository::clone(&git_url, &main_app.under_dir).map_err(Box::from)
+        clone_repo(&git_url, &main_app.under_dir)
     } else {
         Err(Box::from("user canceled"))
     }
 }
 
-pub fn manage_git(main_app: &MainApp) -> Result<(), Box<dyn Error>> {
+// clone with the ssh agent like every other fetch
+pub fn clone_repo(
+    git_url: &str,
+    under_dir: &Path,
+) -> Result<Repository, Box<dyn Error>> {
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(remote_callbacks());
+
+    RepoBuilder::new()
+        .fetch_options(fetch_options)
+        .clone(git_url, under_dir)
+        .map_err(|err| Box::from(format!("cant clone {} {}", git_url, err)))
+}
+
+// use the ssh agent for ssh remotes
+fn remote_callbacks<'a>() -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+
+    callbacks.credentials(|_url, username, allowed| {
+        if allowed.is_ssh_key() {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else {
+            Cred::default()
+        }
+    });
+
+    callbacks
+}
+
+// there is no upstream to pull from or it can not be reached, the only pull
+// errors a link run gets past
+#[derive(Debug)]
+pub struct CantPull(String);
+
+impl fmt::Display for CantPull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for CantPull {}
+
+// an unreachable remote is a CantPull, failed lookups and refused
+// connections come back as net or os errors, bad credentials and every other
+// fetch error are not
+fn fetch_error(remote_name: &str, err: git2::Error) -> Box<dyn Error> {
+    let message = format!("cant fetch {} {}", remote_name, err);
+    let unreachable = matches!(err.class(), ErrorClass::Net | ErrorClass::Os);
+
+    if unreachable && err.code() != ErrorCode::Auth {
+        Box::new(CantPull(message))
+    } else {
+        Box::from(message)
+    }
+}
+
+// fetch the upstream of the checked out branch and fast forward to it,
+// returns true if the branch moved
+pub fn pull_repo(
+    repo: &Repository,
+    dry_run: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(Box::new(CantPull(
+            "under_dir is not on a branch".to_string(),
+        )));
+    }
+
+    let branch_ref = head.name()?.to_string();
+    let branch_name = head.shorthand()?;
+
+    let remote_name =
+        repo.branch_upstream_remote(&branch_ref).map_err(|_| {
+            CantPull(format!("no remote tracked by {}", branch_name))
+        })?;
+    let remote_name = remote_name.as_str()?;
+
+    let upstream_name = repo.branch_upstream_name(&branch_ref)?;
+    let upstream_name = upstream_name.as_str()?;
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(remote_callbacks());
+
+    // an empty list uses the refspecs set for the remote
+    repo.find_remote(remote_name)?
+        .fetch(&[] as &[&str], Some(&mut fetch_options), None)
+        .map_err(|err| fetch_error(remote_name, err))?;
+
+    let upstream = repo.find_reference(upstream_name)?;
+    let upstream_commit = repo.reference_to_annotated_commit(&upstream)?;
+    let (analysis, _) = repo.merge_analysis(&[&upstream_commit])?;
+
+    if analysis.is_up_to_date() {
+        return Ok(false);
+    }
+
+    if !analysis.is_fast_forward() {
+        let local_id = head.target().ok_or("cant get local commit")?;
+        let (ahead, behind) =
+            repo.graph_ahead_behind(local_id, upstream_commit.id())?;
+
+        return Err(Box::from(format!(
+            "{} has diverged from {}, {} local and {} remote commit[s] \
+             need to be merged by hand",
+            branch_name, upstream_name, ahead, behind
+        )));
+    }
+
+    if dry_run {
+        info!(
+            "would fast forward {} to {}",
+            branch_name,
+            upstream_commit.id()
+        );
+        return Ok(true);
+    }
+
+    // checkout first so local edits stop the pull before the branch moves
+    let upstream_object = repo.find_object(upstream_commit.id(), None)?;
+    repo.checkout_tree(&upstream_object, Some(CheckoutBuilder::new().safe()))
+        .map_err(|err| format!("cant fast forward {} {}", branch_name, err))?;
+
+    repo.find_reference(&branch_ref)?.set_target(
+        upstream_commit.id(),
+        &format!("manage: fast forward to {}", upstream_name),
+    )?;
+
+    Ok(true)
+}
+
+// list the changed files by package, the package being the first dir under
+// under_dir, like "polybar_M: config; zsh_all: zshrc, zshrc.d/alias"
+pub fn sync_message(changed: &[PathBuf]) -> String {
+    let mut packages: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for path in changed {
+        let mut parts = path.components();
+        let first = parts
+            .next()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let rest = parts.as_path().to_string_lossy().into_owned();
+
+        // files at the top of under_dir are listed on there own
+        if rest.is_empty() {
+            packages.entry(String::new()).or_default().push(first);
+        } else {
+            packages.entry(first).or_default().push(rest);
+        }
+    }
+
+    packages
+        .iter()
+        .map(|(package, files)| {
+            if package.is_empty() {
+                files.join(", ")
+            } else {
+                format!("{}: {}", package, files.join(", "))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+// stage every change to a tracked file and commit it, new files are only
+// listed unless untracked is set so swap files and stray plain text are not
+// pushed, returns false when there was nothing to commit
+pub fn commit_changes(
+    repo: &Repository,
+    dry_run: bool,
+    untracked: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let mut status_options = StatusOptions::new();
+    status_options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true);
+
+    let statuses = repo.statuses(Some(&mut status_options))?;
+
+    let (new, tracked): (Vec<_>, Vec<_>) = statuses
+        .iter()
+        .filter(|entry| !entry.status().is_ignored())
+        .partition(|entry| entry.status().is_wt_new());
+
+    let new: Vec<PathBuf> = new
+        .iter()
+        .filter_map(|entry| entry.path().ok().map(PathBuf::from))
+        .collect();
+
+    let mut changed: Vec<PathBuf> = tracked
+        .iter()
+        .filter_map(|entry| entry.path().ok().map(PathBuf::from))
+        .collect();
+
+    if untracked {
+        changed.extend(new);
+    } else if !new.is_empty() {
+        let new: Vec<String> =
+            new.iter().map(|path| path.display().to_string()).collect();
+
+        warn!(
+            "not committing new files, use --untracked to add them: {}",
+            new.join(", ")
+        );
+    }
+
+    if changed.is_empty() {
+        return Ok(false);
+    }
+
+    let message = sync_message(&changed);
+
+    if dry_run {
+        info!("would commit {}", message);
+        return Ok(true);
+    }
+
+    let mut index = repo.index()?;
+    if untracked {
+        index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    }
+    index.update_all(["*"].iter(), None)?;
+    index.write()?;
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = repo.head()?.peel_to_commit()?;
+
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now("manage", "manage@localhost"))?;
+
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &[&parent],
+    )?;
+
+    Ok(true)
+}
+
+// push the checked out branch to the branch it tracks, returns false when
+// there was nothing to push
+pub fn push_repo(
+    repo: &Repository,
+    dry_run: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let head = repo.head()?;
+    let branch_ref = head.name()?.to_string();
+    let branch_name = head.shorthand()?;
+
+    let remote_name = repo
+        .branch_upstream_remote(&branch_ref)
+        .map_err(|_| format!("no remote tracked by {}", branch_name))?;
+    let remote_name = remote_name.as_str()?;
+
+    let upstream_name = repo.branch_upstream_name(&branch_ref)?;
+    let upstream_id = repo.refname_to_id(upstream_name.as_str()?)?;
+    let local_id = head.target().ok_or("cant get local commit")?;
+
+    let (ahead, _) = repo.graph_ahead_behind(local_id, upstream_id)?;
+    if ahead == 0 {
+        return Ok(false);
+    }
+
+    if dry_run {
+        info!("would push {} commit[s] to {}", ahead, remote_name);
+        return Ok(true);
+    }
+
+    let merge_ref = repo
+        .config()?
+        .get_string(&format!("branch.{}.merge", branch_name))?;
+
+    let mut callbacks = remote_callbacks();
+    callbacks.push_update_reference(|refname, status| match status {
+        Some(msg) => Err(git2::Error::from_str(&format!(
+            "remote rejected {} {}",
+            refname, msg
+        ))),
+        None => Ok(()),
+    });
+
+    let mut push_options = PushOptions::new();
+    push_options.remote_callbacks(callbacks);
+
+    repo.find_remote(remote_name)?
+        .push(
+            &[&format!("{}:{}", branch_ref, merge_ref)],
+            Some(&mut push_options),
+        )
+        .map_err(|err| format!("cant push to {} {}", remote_name, err))?;
+
+    Ok(true)
+}
+
+// pull first so our commit lands on top of the remote
+pub fn sync_repo(
+    repo: &Repository,
+    dry_run: bool,
+    untracked: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let pulled = pull_repo(repo, dry_run)?;
+    let committed = commit_changes(repo, dry_run, untracked)?;
+    let pushed = if dry_run && committed {
+        info!("would push the new commit");
+        true
+    } else {
+        push_repo(repo, dry_run)?
+    };
+
+    Ok(pulled || committed || pushed)
+}
+
+// the commit checked out in under_dir or an empty string when there is no
+// repo
+pub fn head_commit_id(under_dir: &Path) -> String {
+    let repo = match Repository::open(under_dir) {
+        Ok(repo) => repo,
+        Err(_) => return String::new(),
+    };
+
+    repo.head()
+        .and_then(|head| head.peel_to_commit())
+        .map(|commit| commit.id().to_string())
+        .unwrap_or_default()
+}
+
+pub fn manage_git(main_app: &MainApp) -> Result<(), ManageError> {
+    // every error here is about the repo in under_dir
+    sync_under_dir(main_app).map_err(|err| match ManageError::from(err) {
+        ManageError::Git { message, .. } | ManageError::Other(message) => {
+            ManageError::Git {
+                path: Some(main_app.under_dir.clone()),
+                message,
+            }
+        }
+        err => err,
+    })
+}
+
+// links can still be made from what is checked out when there is no
+// upstream or it can not be reached, any other pull error stops the run
+fn pull_for_links(
+    repo: &Repository,
+    dry_run: bool,
+) -> Result<bool, Box<dyn Error>> {
+    match pull_repo(repo, dry_run) {
+        Err(err) if err.is::<CantPull>() => {
+            warn!("cant pull, using what is checked out: {}", err);
+            Ok(false)
+        }
+        pulled => pulled,
+    }
+}
+
+fn sync_under_dir(main_app: &MainApp) -> Result<(), Box<dyn Error>> {
+    // status only looks at the current state
+    if main_app.action == Action::Status {
+        return Ok(());
+    }
+
     let repo = match Repository::open(&main_app.under_dir) {
         Err(err) => {
-            eprintln!("{}", err);
+            warn!("{}", err);
             initial_clone(main_app)?
         }
         Ok(repo) => repo,
     };
 
-    // more git stuff here
+    if main_app.action == Action::Sync {
+        if sync_repo(&repo, main_app.dry_run, main_app.untracked)? {
+            debug!("synced repo");
+        } else {
+            debug!("nothing to sync");
+        }
+    } else if pull_for_links(&repo, main_app.dry_run)? {
+        debug!("pulled new commits");
+    } else {
+        debug!("repo up to date");
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use git2::{Commit, Oid, Signature};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn commit_file(repo: &Repository, name: &str, contents: &str) -> Oid {
+        let file_path = repo.workdir().unwrap().join(name);
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(file_path, contents).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("test", "test@test").unwrap();
+
+        let parents: Vec<Commit> = match repo.head() {
+            Ok(head) => vec![head.peel_to_commit().unwrap()],
+            Err(_) => vec![],
+        };
+        let parent_refs: Vec<&Commit> = parents.iter().collect();
+
+        repo.commit(Some("HEAD"), &sig, &sig, name, &tree, &parent_refs)
+            .unwrap()
+    }
+
+    fn push_head(repo: &Repository) {
+        let head = repo.head().unwrap();
+        let refspec = format!("{0}:{0}", head.name().unwrap());
+
+        let mut remote = repo.find_remote("origin").unwrap();
+        remote.push(&[&refspec], None).unwrap();
+    }
+
+    // make a bare remote, a repo that pushes to it and a clone of it to act
+    // as the under_dir
+    fn setup_remote<T>(test: T)
+    where
+        T: FnOnce(&Repository, &Repository),
+    {
+        let temp_path = tempdir().unwrap().into_path();
+        let bare_path = temp_path.join("remote.git");
+
+        let bare = Repository::init_bare(&bare_path).unwrap();
+        let work = Repository::init(temp_path.join("work")).unwrap();
+        work.remote("origin", bare_path.to_str().unwrap()).unwrap();
+
+        commit_file(&work, "zshrc", "first");
+        push_head(&work);
+        bare.set_head(work.head().unwrap().name().unwrap()).unwrap();
+
+        let under = Repository::clone(
+            bare_path.to_str().unwrap(),
+            temp_path.join("under"),
+        )
+        .unwrap();
+
+        test(&work, &under);
+    }
+
+    fn read_under(under: &Repository, name: &str) -> String {
+        fs::read_to_string(under.workdir().unwrap().join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_pull_repo_fast_forward() {
+        setup_remote(|work, under| {
+            commit_file(work, "zshrc", "second");
+            push_head(work);
+
+            match pull_repo(under, false) {
+                Ok(moved) => assert!(moved, "didn't fast forward"),
+                Err(err) => panic!("cant pull {}", err),
+            }
+
+            assert_eq!(read_under(under, "zshrc"), "second");
+
+            match pull_repo(under, false) {
+                Ok(moved) => assert!(!moved, "moved when up to date"),
+                Err(err) => panic!("cant pull {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_pull_repo_dry_run() {
+        setup_remote(|work, under| {
+            commit_file(work, "zshrc", "second");
+            push_head(work);
+
+            match pull_repo(under, true) {
+                Ok(moved) => assert!(moved, "didn't see new commits"),
+                Err(err) => panic!("cant pull {}", err),
+            }
+
+            assert_eq!(read_under(under, "zshrc"), "first");
+        })
+    }
+
+    #[test]
+    fn test_pull_repo_diverged() {
+        setup_remote(|work, under| {
+            commit_file(work, "zshrc", "second");
+            push_head(work);
+
+            commit_file(under, "i3_conf", "local");
+
+            match pull_repo(under, false) {
+                Ok(_) => panic!("pulled diverged history"),
+                Err(err) => assert!(
+                    format!("{}", err).contains("has diverged"),
+                    "{}",
+                    err
+                ),
+            }
+
+            match pull_for_links(under, false) {
+                Ok(_) => panic!("linked over diverged history"),
+                Err(err) => assert!(!err.is::<CantPull>(), "{}", err),
+            }
+
+            assert_eq!(read_under(under, "zshrc"), "first");
+        })
+    }
+
+    #[test]
+    fn test_pull_for_links_offline() {
+        let temp_path = tempdir().unwrap().into_path();
+        let repo = Repository::init(temp_path.join("under")).unwrap();
+        commit_file(&repo, "zshrc", "first");
+
+        // no upstream to pull from is only a warning
+        assert!(pull_repo(&repo, false).is_err());
+        match pull_for_links(&repo, false) {
+            Ok(moved) => assert!(!moved, "moved with no upstream"),
+            Err(err) => panic!("no upstream stopped the links {}", err),
+        }
+
+        // and so is a remote that can not be reached
+        setup_remote(|_, under| {
+            under
+                .remote_set_url("origin", "http://127.0.0.1:1/remote.git")
+                .unwrap();
+
+            match pull_for_links(under, false) {
+                Ok(moved) => assert!(!moved, "moved while offline"),
+                Err(err) => panic!("offline stopped the links {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_pull_for_links_local_edits() {
+        setup_remote(|work, under| {
+            commit_file(work, "zshrc", "second");
+            push_head(work);
+
+            let under_zshrc = under.workdir().unwrap().join("zshrc");
+            fs::write(&under_zshrc, "edited").unwrap();
+
+            // linking the old files would hide the edit from the pull
+            match pull_for_links(under, false) {
+                Ok(_) => panic!("linked over a failed checkout"),
+                Err(err) => assert!(!err.is::<CantPull>(), "{}", err),
+            }
+
+            assert_eq!(read_under(under, "zshrc"), "edited");
+        })
+    }
+
+    #[test]
+    fn test_sync_message() {
+        let changed: Vec<PathBuf> = [
+            "zsh_all/zshrc",
+            "polybar_M/.config/polybar/config",
+            "zsh_all/zshrc.d/alias",
+            "README.md",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        assert_eq!(
+            sync_message(&changed),
+            "README.md; polybar_M: .config/polybar/config; \
+             zsh_all: zshrc, zshrc.d/alias"
+        );
+    }
+
+    #[test]
+    fn test_sync_repo() {
+        setup_remote(|work, under| {
+            commit_file(under, "zsh_all/zshrc", "tracked");
+            push_head(under);
+
+            let under_path = under.workdir().unwrap();
+            fs::write(under_path.join("zsh_all/zshrc"), "edited").unwrap();
+            fs::create_dir_all(under_path.join("polybar_M")).unwrap();
+            fs::write(under_path.join("polybar_M/config"), "new").unwrap();
+
+            match sync_repo(under, false, false) {
+                Ok(did_work) => assert!(did_work, "didn't sync"),
+                Err(err) => panic!("cant sync {}", err),
+            }
+
+            let remote_url = work.find_remote("origin").unwrap();
+            let remote = Repository::open(remote_url.url().unwrap()).unwrap();
+            let remote_head = || {
+                remote
+                    .head()
+                    .unwrap()
+                    .peel_to_commit()
+                    .unwrap()
+                    .message()
+                    .unwrap()
+                    .to_string()
+            };
+
+            // new files wait for --untracked
+            assert_eq!(remote_head(), "zsh_all: zshrc");
+
+            match sync_repo(under, false, true) {
+                Ok(did_work) => assert!(did_work, "didn't sync new files"),
+                Err(err) => panic!("cant sync {}", err),
+            }
+
+            assert_eq!(remote_head(), "polybar_M: config");
+
+            match sync_repo(under, false, true) {
+                Ok(did_work) => assert!(!did_work, "synced a clean repo"),
+                Err(err) => panic!("cant sync {}", err),
+            }
+        })
+    }
+}
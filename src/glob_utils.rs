@@ -0,0 +1,324 @@
+use std::error::Error;
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Literal(char),
+    AnyMany,
+    AnyOne,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(lit) => *lit == c,
+            Token::AnyMany | Token::AnyOne => true,
+            Token::Class { negated, ranges } => {
+                let found = ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi);
+                found != *negated
+            }
+        }
+    }
+}
+
+// find the index of the brace closing the one at start, skipping escapes and
+// nested braces
+fn find_close_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = start;
+
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+
+        index += 1;
+    }
+
+    None
+}
+
+// split the inside of a brace on top level commas
+fn split_alternatives(chars: &[char]) -> Vec<String> {
+    let mut to_ret: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+
+        match c {
+            '\\' if index + 1 < chars.len() => {
+                current.push(c);
+                current.push(chars[index + 1]);
+                index += 1;
+            }
+            '{' => {
+                depth += 1;
+                current.push(c);
+            }
+            '}' => {
+                depth -= 1;
+                current.push(c);
+            }
+            ',' if depth == 0 => to_ret.push(current.split_off(0)),
+            _ => current.push(c),
+        }
+
+        index += 1;
+    }
+
+    to_ret.push(current);
+
+    to_ret
+}
+
+// turn {a,b} alternation in to a list of plain patterns
+fn expand_braces(pattern: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let chars: Vec<char> = pattern.chars().collect();
+
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => {
+                let close =
+                    find_close_brace(&chars, index).ok_or_else(|| {
+                        format!("unclosed {{ in pattern {}", pattern)
+                    })?;
+
+                let prefix: String = chars[..index].iter().collect();
+                let suffix: String = chars[close + 1..].iter().collect();
+
+                let mut to_ret: Vec<String> = vec![];
+                for alt in split_alternatives(&chars[index + 1..close]) {
+                    let joined = format!("{}{}{}", prefix, alt, suffix);
+                    to_ret.append(&mut expand_braces(&joined)?);
+                }
+
+                return Ok(to_ret);
+            }
+            _ => {}
+        }
+
+        index += 1;
+    }
+
+    Ok(vec![pattern.to_string()])
+}
+
+fn parse_class(
+    chars: &[char],
+    start: usize,
+    pattern: &str,
+) -> Result<(Token, usize), Box<dyn Error>> {
+    let mut index = start + 1;
+
+    let negated =
+        index < chars.len() && (chars[index] == '!' || chars[index] == '^');
+    if negated {
+        index += 1;
+    }
+
+    let mut ranges: Vec<(char, char)> = vec![];
+    let mut first = true;
+
+    loop {
+        let c = *chars
+            .get(index)
+            .ok_or_else(|| format!("unclosed [ in pattern {}", pattern))?;
+
+        // a ] right after the [ is part of the class
+        if c == ']' && !first {
+            return Ok((Token::Class { negated, ranges }, index));
+        }
+
+        let lo = if c == '\\' {
+            index += 1;
+            *chars
+                .get(index)
+                .ok_or_else(|| format!("bad escape in pattern {}", pattern))?
+        } else {
+            c
+        };
+
+        let is_range = chars.get(index + 1) == Some(&'-')
+            && chars.get(index + 2).is_some_and(|hi| *hi != ']');
+
+        if is_range {
+            let hi = chars[index + 2];
+            if hi < lo {
+                return Err(Box::from(format!(
+                    "bad range {}-{} in pattern {}",
+                    lo, hi, pattern
+                )));
+            }
+
+            ranges.push((lo, hi));
+            index += 3;
+        } else {
+            ranges.push((lo, lo));
+            index += 1;
+        }
+
+        first = false;
+    }
+}
+
+fn tokenize(pattern: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut to_ret: Vec<Token> = vec![];
+
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => {
+                index += 1;
+                let c = chars.get(index).ok_or_else(|| {
+                    format!("bad escape in pattern {}", pattern)
+                })?;
+
+                to_ret.push(Token::Literal(*c));
+            }
+            '*' => {
+                if to_ret.last() != Some(&Token::AnyMany) {
+                    to_ret.push(Token::AnyMany);
+                }
+            }
+            '?' => to_ret.push(Token::AnyOne),
+            '[' => {
+                let (class, end) = parse_class(&chars, index, pattern)?;
+                to_ret.push(class);
+                index = end;
+            }
+            c => to_ret.push(Token::Literal(c)),
+        }
+
+        index += 1;
+    }
+
+    Ok(to_ret)
+}
+
+// backtrack to the last * when a match fails
+fn match_tokens(tokens: &[Token], name: &[char]) -> bool {
+    let mut tok_index = 0;
+    let mut name_index = 0;
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while name_index < name.len() {
+        if let Some(token) = tokens.get(tok_index) {
+            if *token == Token::AnyMany {
+                last_star = Some((tok_index, name_index));
+                tok_index += 1;
+                continue;
+            } else if token.matches(name[name_index]) {
+                tok_index += 1;
+                name_index += 1;
+                continue;
+            }
+        }
+
+        match last_star {
+            Some((star_tok, star_name)) => {
+                tok_index = star_tok + 1;
+                name_index = star_name + 1;
+                last_star = Some((star_tok, star_name + 1));
+            }
+            None => return false,
+        }
+    }
+
+    tokens[tok_index..].iter().all(|tok| *tok == Token::AnyMany)
+}
+
+// make sure a pattern can be used with glob_match
+pub fn check_glob(pattern: &str) -> Result<(), Box<dyn Error>> {
+    for alt in expand_braces(pattern)? {
+        tokenize(&alt)?;
+    }
+
+    Ok(())
+}
+
+// match a name against a glob pattern
+//
+// supports *, ?, classes like [OM], [a-z] or [!O], alternation like {all,M}
+// and \ to escape any of them
+pub fn glob_match(pattern: &str, name: &str) -> Result<bool, Box<dyn Error>> {
+    let name_chars: Vec<char> = name.chars().collect();
+
+    for alt in expand_braces(pattern)? {
+        if match_tokens(&tokenize(&alt)?, &name_chars) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_match(pattern: &str, name: &str) -> bool {
+        match glob_match(pattern, name) {
+            Ok(found) => found,
+            Err(err) => panic!("bad pattern {} {}", pattern, err),
+        }
+    }
+
+    #[test]
+    fn test_glob_star() {
+        assert!(is_match("*_all", "zsh_all"));
+        assert!(is_match("all_*", "all_zsh"));
+        assert!(is_match("zsh_*_M", "zsh_work_M"));
+        assert!(is_match("*", ""));
+        assert!(is_match("*_*_*", "a_b_c"));
+        assert!(!is_match("*_all", "zsh_al"));
+        assert!(!is_match("zsh_*_M", "zsh_work_O"));
+    }
+
+    #[test]
+    fn test_glob_one_and_class() {
+        assert!(is_match("polybar_?", "polybar_M"));
+        assert!(!is_match("polybar_?", "polybar_MO"));
+        assert!(is_match("polybar_[OM]", "polybar_O"));
+        assert!(!is_match("polybar_[OM]", "polybar_X"));
+        assert!(is_match("polybar_[!OM]", "polybar_X"));
+        assert!(is_match("term_[a-c]", "term_b"));
+        assert!(is_match("term_[]]", "term_]"));
+        assert!(is_match("term_\\*", "term_*"));
+        assert!(!is_match("term_\\*", "term_x"));
+    }
+
+    #[test]
+    fn test_glob_braces() {
+        assert!(is_match("*_{all,M}", "zsh_all"));
+        assert!(is_match("*_{all,M}", "polybar_M"));
+        assert!(!is_match("*_{all,M}", "polybar_O"));
+        assert!(is_match("{i3,zsh}_{desk,lap{top,}}", "zsh_lap"));
+        assert!(is_match("{i3,zsh}_{desk,lap{top,}}", "i3_laptop"));
+    }
+
+    #[test]
+    fn test_glob_bad_patterns() {
+        assert!(check_glob("*_[OM").is_err(), "unclosed class");
+        assert!(check_glob("*_{all,M").is_err(), "unclosed brace");
+        assert!(check_glob("*_\\").is_err(), "trailing escape");
+        assert!(check_glob("*_[z-a]").is_err(), "backwards range");
+        assert!(check_glob("*_{all,[OM]}").is_ok(), "good pattern");
+    }
+}
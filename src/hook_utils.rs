@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::{debug, info};
+use serde::Deserialize;
+
+use crate::{output_utils::OutputFormat, MainApp};
+
+// scripts named after the hook in this dir at the top of a package, the dir
+// itself is never linked
+pub const HOOKS_DIR: &str = ".manage_hooks";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HookKind {
+    PreLink,
+    PostLink,
+    PreUnlink,
+    PostUnlink,
+}
+
+impl fmt::Display for HookKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind_str = match self {
+            HookKind::PreLink => "pre_link",
+            HookKind::PostLink => "post_link",
+            HookKind::PreUnlink => "pre_unlink",
+            HookKind::PostUnlink => "post_unlink",
+        };
+
+        write!(f, "{}", kind_str)
+    }
+}
+
+// shell commands for one package from a [hooks.PACKAGE] table
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HookConf {
+    pub pre_link: Option<String>,
+    pub post_link: Option<String>,
+    pub pre_unlink: Option<String>,
+    pub post_unlink: Option<String>,
+}
+
+impl HookConf {
+    fn command(&self, kind: HookKind) -> Option<&String> {
+        match kind {
+            HookKind::PreLink => self.pre_link.as_ref(),
+            HookKind::PostLink => self.post_link.as_ref(),
+            HookKind::PreUnlink => self.pre_unlink.as_ref(),
+            HookKind::PostUnlink => self.post_unlink.as_ref(),
+        }
+    }
+}
+
+// package name to its hooks
+pub type PackageHooks = BTreeMap<String, HookConf>;
+
+#[derive(Debug, PartialEq)]
+enum Hook {
+    Script(PathBuf),
+    Command(String),
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hook::Script(script) => write!(f, "{}", script.display()),
+            Hook::Command(command) => write!(f, "{}", command),
+        }
+    }
+}
+
+// a command in the config replaces a script in the package
+fn find_hook(
+    package_dir: &Path,
+    hook_conf: Option<&HookConf>,
+    kind: HookKind,
+) -> Option<Hook> {
+    if let Some(command) = hook_conf.and_then(|conf| conf.command(kind)) {
+        return Some(Hook::Command(command.clone()));
+    }
+
+    let script = package_dir.join(HOOKS_DIR).join(kind.to_string());
+
+    if script.is_file() {
+        Some(Hook::Script(script))
+    } else {
+        None
+    }
+}
+
+fn package_of(package_dir: &Path) -> String {
+    package_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// run the hook for a package if it has one, a hook that fails stops the run
+pub fn run_hook(
+    main_app: &MainApp,
+    package_dir: &Path,
+    kind: HookKind,
+) -> Result<bool, Box<dyn Error>> {
+    let package = package_of(package_dir);
+
+    let hook = match find_hook(package_dir, main_app.hooks.get(&package), kind)
+    {
+        Some(hook) => hook,
+        None => return Ok(false),
+    };
+
+    if main_app.dry_run {
+        info!("would run {} hook for {}: {}", kind, package, hook);
+        return Ok(true);
+    }
+
+    debug!("running {} hook for {}: {}", kind, package, hook);
+
+    let mut command = match &hook {
+        Hook::Script(script) => Command::new(script),
+        Hook::Command(command) => {
+            let mut sh = Command::new("sh");
+            sh.arg("-c").arg(command);
+            sh
+        }
+    };
+
+    command
+        .current_dir(package_dir)
+        .env("MANAGE_HOOK", kind.to_string())
+        .env("MANAGE_ACTION", main_app.action.to_string())
+        .env("MANAGE_PACKAGE", &package)
+        .env("MANAGE_PACKAGE_DIR", package_dir)
+        .env("MANAGE_UNDER_DIR", &main_app.under_dir)
+        .env("MANAGE_UPPER_DIR", &main_app.upper_dir);
+
+    // stdout only holds the json records
+    if main_app.format == OutputFormat::Json {
+        command.stdout(io::stderr());
+    }
+
+    let status = command.status().map_err(|err| {
+        format!("cant run {} hook for {} {}: {}", kind, package, hook, err)
+    })?;
+
+    if status.success() {
+        Ok(true)
+    } else {
+        Err(Box::from(format!(
+            "{} hook for {} failed with {}",
+            kind, package, status
+        )))
+    }
+}
+
+// run the hook for every package in order and stop at the first failure
+pub fn run_hooks(
+    main_app: &MainApp,
+    package_dirs: &[PathBuf],
+    kind: HookKind,
+) -> Result<(), Box<dyn Error>> {
+    for package_dir in package_dirs {
+        run_hook(main_app, package_dir, kind)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_find_hook() {
+        let package_dir = tempdir().unwrap().into_path();
+        let script = package_dir.join(HOOKS_DIR).join("post_link");
+
+        fs::create_dir_all(script.parent().unwrap()).unwrap();
+        fs::write(&script, "#!/bin/sh\nfc-cache -f\n").unwrap();
+
+        assert_eq!(
+            find_hook(&package_dir, None, HookKind::PostLink),
+            Some(Hook::Script(script))
+        );
+        assert_eq!(find_hook(&package_dir, None, HookKind::PreLink), None);
+
+        let conf = HookConf {
+            post_link: Some("i3-msg reload".to_string()),
+            ..HookConf::default()
+        };
+
+        assert_eq!(
+            find_hook(&package_dir, Some(&conf), HookKind::PostLink),
+            Some(Hook::Command("i3-msg reload".to_string()))
+        );
+    }
+}
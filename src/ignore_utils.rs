@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::glob_utils::{check_glob, glob_match};
+
+// a file of gitignore style patterns at the root of under_dir or of a
+// package, it is never linked its self
+pub const IGNORE_FILE: &str = ".manageignore";
+
+// one line of an ignore file
+#[derive(Debug)]
+struct IgnoreRule {
+    // the pattern split on /, ** matches any number of dirs
+    parts: Vec<String>,
+    // ! re includes what an earlier pattern ignored
+    negated: bool,
+    // a trailing / only matches dirs
+    dir_only: bool,
+    // a / any where but the end matches from the base instead of any depth
+    anchored: bool,
+}
+
+// ** can stand for no dirs or many
+fn match_parts(parts: &[String], path: &[String]) -> bool {
+    match parts.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_parts(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((path_first, path_rest)) => {
+                glob_match(first, path_first).unwrap_or(false)
+                    && match_parts(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+
+        let parts: Vec<String> = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(|part| part.to_string())
+            .collect();
+
+        for part in &parts {
+            if part.is_empty() {
+                return Err(Box::from(format!("empty pattern {}", line)));
+            }
+
+            if part != "**" {
+                check_glob(part)?;
+            }
+        }
+
+        Ok(Some(IgnoreRule {
+            parts,
+            negated,
+            dir_only,
+            anchored,
+        }))
+    }
+
+    fn matches(&self, path: &[String], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            match_parts(&self.parts, path)
+        } else {
+            match path.last() {
+                Some(name) => glob_match(&self.parts[0], name).unwrap_or(false),
+                None => false,
+            }
+        }
+    }
+}
+
+// make sure a pattern can be used in an ignore list
+pub fn check_ignore(pattern: &str) -> Result<(), Box<dyn Error>> {
+    IgnoreRule::parse(pattern).map(|_| ())
+}
+
+// the patterns from one place and the dir they start from
+#[derive(Debug)]
+struct IgnoreList {
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreList {
+    fn new<'a, I>(base: &Path, lines: I, source: &str) -> Result<Self, String>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut rules: Vec<IgnoreRule> = vec![];
+
+        for (index, line) in lines.enumerate() {
+            match IgnoreRule::parse(line) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) => {}
+                Err(err) => {
+                    return Err(format!(
+                        "bad ignore pattern in {} line {}: {}",
+                        source,
+                        index + 1,
+                        err
+                    ))
+                }
+            }
+        }
+
+        Ok(IgnoreList {
+            base: base.to_owned(),
+            rules,
+        })
+    }
+
+    fn from_file(base: &Path) -> Result<Option<Self>, String> {
+        let ignore_file = base.join(IGNORE_FILE);
+
+        if !ignore_file.is_file() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&ignore_file)
+            .map_err(|err| format!("cant read {:?} {}", ignore_file, err))?;
+
+        let source = ignore_file.display().to_string();
+
+        IgnoreList::new(base, content.lines(), &source).map(Some)
+    }
+
+    // Some(true) when the last matching pattern ignores the path and
+    // Some(false) when it re includes it
+    fn state(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let parts: Vec<String> = path
+            .strip_prefix(&self.base)
+            .ok()?
+            .components()
+            .map(|comp| comp.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&parts, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+// the global list from the config, the ignore file at the root of under_dir
+// then the one in each package, later lists win
+#[derive(Default, Debug)]
+pub struct IgnoreRules {
+    under_dir: PathBuf,
+    lists: Vec<IgnoreList>,
+}
+
+impl IgnoreRules {
+    pub fn new(
+        under_dir: &Path,
+        global: &[String],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut lists: Vec<IgnoreList> = vec![IgnoreList::new(
+            under_dir,
+            global.iter().map(String::as_str),
+            "config",
+        )?];
+
+        if let Some(root_list) = IgnoreList::from_file(under_dir)? {
+            lists.push(root_list);
+        }
+
+        if let Ok(read_under) = fs::read_dir(under_dir) {
+            for entry in read_under.flatten() {
+                let package_dir = entry.path();
+
+                if !package_dir.is_dir() {
+                    continue;
+                }
+
+                if let Some(package_list) = IgnoreList::from_file(&package_dir)?
+                {
+                    lists.push(package_list);
+                }
+            }
+        }
+
+        Ok(IgnoreRules {
+            under_dir: under_dir.to_owned(),
+            lists,
+        })
+    }
+
+    // a path in under_dir is ignored when it or any dir above it is
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        if path.file_name() == Some(IGNORE_FILE.as_ref()) {
+            return true;
+        }
+
+        let relative = match path.strip_prefix(&self.under_dir) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        let mut prefix = self.under_dir.clone();
+        let mut components = relative.components().peekable();
+
+        while let Some(component) = components.next() {
+            prefix.push(component);
+
+            let is_dir = components.peek().is_some() || path.is_dir();
+
+            let ignored = self
+                .lists
+                .iter()
+                .rev()
+                .find_map(|list| list.state(&prefix, is_dir))
+                .unwrap_or(false);
+
+            if ignored {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let under_dir = tempdir().unwrap().into_path();
+        let vim_dir = under_dir.join("vim_all");
+
+        fs::create_dir_all(vim_dir.join(".vim/pack/plug/.git")).unwrap();
+        fs::create_dir_all(vim_dir.join("build")).unwrap();
+        fs::write(under_dir.join(IGNORE_FILE), "README*\n/vim_all/build/\n")
+            .unwrap();
+        fs::write(vim_dir.join(IGNORE_FILE), "# plugins\n**/.git\n*.md\n")
+            .unwrap();
+
+        let rules = match IgnoreRules::new(
+            &under_dir,
+            &["*.swp".to_string(), "!keep.swp".to_string()],
+        ) {
+            Ok(rules) => rules,
+            Err(err) => panic!("cant load ignore rules {}", err),
+        };
+
+        let is_ignored = |path: &str| rules.is_ignored(&vim_dir.join(path));
+
+        assert!(is_ignored("README.md"));
+        assert!(is_ignored(".vim/pack/plug/.git"));
+        assert!(is_ignored(".vim/pack/plug/.git/config"));
+        assert!(is_ignored(".vimrc.swp"));
+        assert!(is_ignored("build"));
+        assert!(is_ignored("notes.md"));
+        assert!(is_ignored(IGNORE_FILE));
+        assert!(!is_ignored("keep.swp"));
+        assert!(!is_ignored(".vimrc"));
+        assert!(!is_ignored(".vim/pack/plug/plugin.vim"));
+        assert!(!rules.is_ignored(&under_dir.join("zsh_all/notes.md")));
+    }
+
+    #[test]
+    fn test_bad_ignore_pattern() {
+        assert!(check_ignore("*.[ch").is_err());
+        assert!(check_ignore("a//b").is_err());
+        assert!(check_ignore("/**/build/").is_ok());
+    }
+}
@@ -0,0 +1,281 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::os::unix;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+use log::{info, warn};
+
+use crate::config_check::check_config;
+use crate::git_utils::clone_repo;
+use crate::my_utils::get_linux_hostname;
+
+// a config committed at the top of the repo is linked in to place instead of
+// writing a starter one
+pub const REPO_CONFIG: &str = "manage.toml";
+
+// the documented config is the starter config
+const FULL_CONFIG: &str = include_str!("../docs/full_config.toml");
+
+pub struct InitOptions<'a> {
+    pub git_url: &'a str,
+    // relative paths start from HOME like under_dir in the config
+    pub under_dir: &'a str,
+    pub config_path: &'a Path,
+    // replace a config that is already there
+    pub force: bool,
+    pub dry_run: bool,
+}
+
+// a bare toml key or a quoted one
+fn toml_key(key: &str) -> String {
+    let bare = key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if bare && !key.is_empty() {
+        key.to_string()
+    } else {
+        format!("{:?}", key)
+    }
+}
+
+// the full config with the under dir and url filled in and one target for
+// this machine
+pub fn starter_config(
+    under_dir: &str,
+    git_url: &str,
+    hostname: &str,
+) -> Result<String, Box<dyn Error>> {
+    let replacements = [
+        (
+            "under_dir = '.dots'".to_string(),
+            format!("under_dir = {:?}", under_dir),
+        ),
+        (
+            "# git_url = ''".to_string(),
+            format!("git_url = {:?}", git_url),
+        ),
+        (
+            "[targets.Monolith]\nfile_patterns = ['*_all', '*_M']".to_string(),
+            format!(
+                "[targets.{}]\nfile_patterns = ['*_all']",
+                toml_key(hostname)
+            ),
+        ),
+        (
+            "[targets.Odimm]\nfile_patterns = ['*_all', '*_O']".to_string(),
+            "# [targets.Odimm]\n# file_patterns = ['*_all', '*_O']".to_string(),
+        ),
+    ];
+
+    let mut config = FULL_CONFIG.to_string();
+
+    for (from, to) in &replacements {
+        if !config.contains(from.as_str()) {
+            return Err(Box::from(format!(
+                "starter config is missing {}",
+                from
+            )));
+        }
+
+        config = config.replacen(from.as_str(), to, 1);
+    }
+
+    Ok(config)
+}
+
+fn under_path(under_dir: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let home =
+        env::var("HOME").map_err(|err| format!("no HOME var {}", err))?;
+
+    Ok(Path::new(&home).join(under_dir.trim_start_matches("~/")))
+}
+
+// clone the repo unless it is already there
+fn init_repo(
+    git_url: &str,
+    under_dir: &Path,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    if Repository::open(under_dir).is_ok() {
+        info!("using the repo already in {:?}", under_dir);
+        return Ok(());
+    }
+
+    let has_files = fs::read_dir(under_dir)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+
+    if has_files {
+        return Err(Box::from(format!(
+            "{:?} has files and is not a repo",
+            under_dir
+        )));
+    }
+
+    if dry_run {
+        info!("would clone {} in to {:?}", git_url, under_dir);
+    } else {
+        clone_repo(git_url, under_dir)?;
+        info!("cloned {} in to {:?}", git_url, under_dir);
+    }
+
+    Ok(())
+}
+
+// set up a new machine, clone the repo then link the config in the repo in
+// to place or write a starter config
+pub fn init_machine(options: &InitOptions) -> Result<(), Box<dyn Error>> {
+    let config_path = options.config_path;
+
+    if config_path.symlink_metadata().is_ok() && !options.force {
+        return Err(Box::from(format!(
+            "config {:?} is already there, give --force to replace it",
+            config_path
+        )));
+    }
+
+    let under_dir = under_path(options.under_dir)?;
+
+    init_repo(options.git_url, &under_dir, options.dry_run)?;
+
+    let repo_config = under_dir.join(REPO_CONFIG);
+
+    if options.dry_run {
+        if repo_config.is_file() {
+            info!("would link {:?} to {:?}", config_path, repo_config);
+        } else {
+            info!("would write a starter config to {:?}", config_path);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if config_path.symlink_metadata().is_ok() {
+        fs::remove_file(config_path).map_err(|err| {
+            format!("cant replace config {:?} {}", config_path, err)
+        })?;
+    }
+
+    if repo_config.is_file() {
+        unix::fs::symlink(&repo_config, config_path).map_err(|err| {
+            format!("cant link config {:?} {}", config_path, err)
+        })?;
+
+        info!("linked {:?} to {:?}", config_path, repo_config);
+    } else {
+        let hostname = get_linux_hostname().ok_or("cant get host name")?;
+        let config =
+            starter_config(options.under_dir, options.git_url, &hostname)?;
+
+        fs::write(config_path, config).map_err(|err| {
+            format!("cant write config {:?} {}", config_path, err)
+        })?;
+
+        info!("wrote a starter config to {:?}", config_path);
+    }
+
+    // a config from the repo may not fit this machine yet
+    for problem in check_config(config_path)? {
+        warn!("{}:{}", config_path.display(), problem);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use git2::Signature;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::config_check::check_config_str;
+
+    fn commit_all(repo: &Repository) {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+
+        repo.commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_starter_config() {
+        let temp_path = tempdir().unwrap().into_path();
+        let under_dir = temp_path.join(".dots");
+        fs::create_dir_all(under_dir.join("zsh_all")).unwrap();
+
+        let hostname = get_linux_hostname().unwrap();
+        let config = starter_config(
+            &under_dir.display().to_string(),
+            "git@example.com:me/dots.git",
+            &hostname,
+        )
+        .unwrap();
+
+        assert!(config.contains("git_url = \"git@example.com:me/dots.git\""));
+        assert_eq!(
+            check_config_str(&config)
+                .iter()
+                .map(|problem| problem.to_string())
+                .collect::<Vec<String>>(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_init_machine() {
+        let temp_path = tempdir().unwrap().into_path();
+        let origin = temp_path.join("origin");
+        let config_path = temp_path.join("config/manage");
+
+        let repo = Repository::init(&origin).unwrap();
+        fs::create_dir_all(origin.join("zsh_all")).unwrap();
+        fs::write(origin.join("zsh_all/zshrc"), "").unwrap();
+        fs::write(origin.join(REPO_CONFIG), "[base_vars]\n").unwrap();
+        commit_all(&repo);
+
+        let under_dir = temp_path.join("dots");
+        let options = InitOptions {
+            git_url: origin.to_str().unwrap(),
+            under_dir: under_dir.to_str().unwrap(),
+            config_path: &config_path,
+            force: false,
+            dry_run: false,
+        };
+
+        if let Err(err) = init_machine(&options) {
+            panic!("cant init {}", err);
+        }
+
+        assert!(under_dir.join("zsh_all/zshrc").exists());
+        assert_eq!(
+            config_path.read_link().unwrap(),
+            under_dir.join(REPO_CONFIG)
+        );
+
+        // the config is never replaced by accident and a clone is reused
+        assert!(init_machine(&options).is_err(), "replaced config");
+
+        let options = InitOptions {
+            force: true,
+            ..options
+        };
+
+        if let Err(err) = init_machine(&options) {
+            panic!("cant init again {}", err);
+        }
+    }
+}
@@ -3,19 +3,48 @@ extern crate tempfile;
 extern crate toml;
 
 pub mod auto_filters;
+pub mod backup_utils;
+pub mod config_check;
+pub mod copy_utils;
+pub mod error;
 pub mod git_utils;
+pub mod glob_utils;
+pub mod hook_utils;
+pub mod ignore_utils;
+pub mod init_utils;
+pub mod link_manifest;
+pub mod link_status;
+pub mod link_transaction;
+pub mod log_utils;
 pub mod my_utils;
+pub mod output_utils;
+pub mod root_utils;
+pub mod secret_utils;
 pub mod symbolic_link;
+pub mod template_utils;
 pub mod user_config;
 
-use std::error::Error;
+use std::env;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::ArgMatches;
+use log::{debug, log_enabled, Level};
 
-use auto_filters::filter_target_dirs;
-use my_utils::make_then_check_path;
+pub use error::Error;
+
+use auto_filters::{filter_target_dirs, machine_target};
+use backup_utils::make_backup_dir;
+use copy_utils::CopyRule;
+use glob_utils::glob_match;
+use hook_utils::PackageHooks;
+use ignore_utils::IgnoreRules;
+use my_utils::{get_xdg_state_path, make_then_check_path};
+use output_utils::{Output, OutputFormat};
+use root_utils::{xdg_roots, DestRoots};
+use secret_utils::default_key_file;
+use symbolic_link::{ConflictPolicy, LinkStyle};
+use template_utils::{make_template_vars, TemplateVars};
 use user_config::Conf;
 
 #[derive(PartialEq, PartialOrd, Clone, Debug)]
@@ -23,6 +52,17 @@ pub enum Action {
     Make,
     Delete,
     Remake,
+    Sync,
+    Restore,
+    Status,
+    // move files in to a package then link them back
+    Add,
+    // check the config and the setup it points to
+    Doctor,
+    Encrypt,
+    EditSecret,
+    // clone the repo and write the config on a new machine
+    Init,
 }
 
 impl fmt::Display for Action {
@@ -31,32 +71,147 @@ impl fmt::Display for Action {
             Action::Make => "make",
             Action::Delete => "delete",
             Action::Remake => "remake",
+            Action::Sync => "sync",
+            Action::Restore => "restore",
+            Action::Status => "status",
+            Action::Add => "add",
+            Action::Doctor => "doctor",
+            Action::Encrypt => "encrypt",
+            Action::EditSecret => "edit secret",
+            Action::Init => "init",
         };
 
         write!(f, "{}", action_str)
     }
 }
 
+impl Action {
+    // the subcommand first then the old flags that stand for one
+    pub fn from_args(args: &ArgMatches) -> Self {
+        match args.subcommand_name() {
+            Some("link") => return Action::Make,
+            Some("unlink") => return Action::Delete,
+            Some("relink") => return Action::Remake,
+            Some("status") => return Action::Status,
+            Some("add") => return Action::Add,
+            Some("sync") => return Action::Sync,
+            Some("restore") => return Action::Restore,
+            Some("doctor") => return Action::Doctor,
+            Some("encrypt") => return Action::Encrypt,
+            Some("edit-secret") => return Action::EditSecret,
+            Some("init") => return Action::Init,
+            _ => {}
+        }
+
+        if args.is_present("delete") {
+            Action::Delete
+        } else if args.is_present("remake") {
+            Action::Remake
+        } else if args.is_present("sync") {
+            Action::Sync
+        } else if args.is_present("restore") {
+            Action::Restore
+        } else if args.is_present("status") {
+            Action::Status
+        } else if args.is_present("check_config") {
+            Action::Doctor
+        } else if args.is_present("encrypt") {
+            Action::Encrypt
+        } else if args.is_present("edit_secret") {
+            Action::EditSecret
+        } else {
+            Action::Make
+        }
+    }
+
+    // the actions that work on the packages picked for this machine
+    pub fn uses_packages(&self) -> bool {
+        matches!(
+            self,
+            Action::Make
+                | Action::Delete
+                | Action::Remake
+                | Action::Status
+                | Action::Add
+        )
+    }
+}
+
+// the args of the subcommand when there is one, global args like -v given
+// before it are copied in to it
+pub fn action_args<'a, 'b>(args: &'b ArgMatches<'a>) -> &'b ArgMatches<'a> {
+    match args.subcommand() {
+        (_, Some(sub_args)) => sub_args,
+        _ => args,
+    }
+}
+
 #[derive(Debug)]
 pub struct MainApp {
     pub under_dir: PathBuf,
     pub upper_dir: PathBuf,
     pub target_dirs: Vec<PathBuf>,
-    pub verbose_num: u64,
     pub dry_run: bool,
+    // text or a json line for each link
+    pub format: OutputFormat,
+    // where json records go, nothing is written until it is given a writer
+    pub output: Output,
     pub action: Action,
     pub git_url: Option<String>,
+    pub on_conflict: ConflictPolicy,
+    pub link_style: LinkStyle,
+    pub backup_dir: PathBuf,
+    pub manifest_file: PathBuf,
+    pub template_vars: TemplateVars,
+    pub copy_rule: CopyRule,
+    // copy local edits of copied files back in to under_dir
+    pub write_back: bool,
+    // sync commits files git does not track yet
+    pub untracked: bool,
+    pub hooks: PackageHooks,
+    pub ignore: IgnoreRules,
+    // globs of packages that get real dirs instead of dir links
+    pub no_folding: Vec<String>,
+    // where each package is linked to when not upper_dir
+    pub roots: DestRoots,
+    // decrypts .enc files in to upper_dir
+    pub secret_key_file: PathBuf,
+    // the paths to move in to the package when adding
+    pub to_add: Vec<PathBuf>,
+}
+
+fn log_target_dirs(target_dirs: &[PathBuf]) {
+    if log_enabled!(Level::Debug) && !target_dirs.is_empty() {
+        let target_iter: Vec<String> = target_dirs
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+
+        let msg_string = my_utils::vec_to_string("", &target_iter);
+
+        debug!("got target dir[s] {}", &msg_string);
+    }
 }
 
 impl MainApp {
     pub fn new(
         args: ArgMatches,
-        config: Result<Conf, Box<dyn Error>>,
-    ) -> Result<Self, Box<dyn Error>> {
-        let verbose_num = args.occurrences_of("verbose");
+        config: Result<Conf, Error>,
+    ) -> Result<Self, Error> {
+        // init links like link once the repo and config are there
+        let action = match Action::from_args(&args) {
+            Action::Init => Action::Make,
+            action => action,
+        };
+        let args = action_args(&args);
 
         let dry_run = args.is_present("dryrun");
 
+        let format: OutputFormat = match args.value_of("format") {
+            Some(format) => format.parse()?,
+            None => OutputFormat::Text,
+        };
+
         let initial_target_path: Option<PathBuf> = args
             .value_of("target")
             .map(|val| make_then_check_path(&[val]))
@@ -66,7 +221,8 @@ impl MainApp {
             Some(conf)
         } else if initial_target_path.is_none() {
             if let Err(err) = config {
-                return Err(Box::from(format!("config and cli args: {}", err)));
+                // the config is only needed without a target on the cli
+                return Err(err);
             } else {
                 None
             }
@@ -74,8 +230,8 @@ impl MainApp {
             None
         };
 
-        if verbose_num > 0 && config.is_some() {
-            println!("got config");
+        if config.is_some() {
+            debug!("got config");
         }
 
         let init_upper: Option<PathBuf> = args
@@ -83,15 +239,7 @@ impl MainApp {
             .map(|val| make_then_check_path(&[val]))
             .unwrap_or(None);
 
-        let action = if args.is_present("delete") {
-            Action::Delete
-        } else if args.is_present("remake") {
-            Action::Remake
-        } else {
-            Action::Make
-        };
-
-        let gurl = args.value_of("git_url").map(|val| val.to_string());
+        let gurl = args.value_of("giturl").map(|val| val.to_string());
 
         // if initial_target_path given then use .. as upper_dir
         let upper_dir: PathBuf = if let Some(ini_up) = &initial_target_path {
@@ -102,19 +250,11 @@ impl MainApp {
                 .ok_or("can't get auto upper dir")?
         } else if let Some(upper) = init_upper {
             upper
-        } else if let Some(ini_target) = &initial_target_path {
-            let mut initial_anc = ini_target.ancestors();
-            initial_anc.next();
-            initial_anc.next().unwrap().to_path_buf()
         } else {
-            return Err(Box::from("somethings fucked up"));
+            return Err(Error::from("somethings fucked up"));
         };
 
-        if verbose_num == 1 {
-            println!("got upper dir");
-        } else if verbose_num > 1 {
-            println!("got upper dir {:?}", upper_dir);
-        }
+        debug!("got upper dir {:?}", upper_dir);
 
         let under_dir: PathBuf = if let Some(ini_path) = &initial_target_path {
             // upper can be else where
@@ -122,30 +262,27 @@ impl MainApp {
         } else if let Some(conf) = &config {
             upper_dir.join(&conf.under_dir)
         } else {
-            return Err(Box::from("no under in config or cli"));
+            return Err(Error::from("no under in config or cli"));
         };
 
         let target_dirs: Vec<PathBuf> =
-            if let Some(ini_target) = &initial_target_path {
+            // add works on the one package it was given even a new one
+            if let Some(package) = args.value_of("package") {
+                if Path::new(package).components().count() != 1 {
+                    return Err(Error::from(format!("bad package {}", package)));
+                }
+
+                vec![under_dir.join(package)]
+            } else if let Some(ini_target) = &initial_target_path {
                 vec![ini_target.clone()]
-            } else if let Some(conf) = &config {
-                filter_target_dirs(&under_dir, &conf)?
+            } else if config.is_some() {
+                // picked by select_target_dirs once under_dir is pulled
+                vec![]
             } else {
-                return Err(Box::from("no config for auto"));
+                return Err(Error::from("no config for auto"));
             };
 
-        if verbose_num == 1 {
-            println!("got target_dirs dir[s]");
-        } else if verbose_num > 1 {
-            let target_iter: Vec<String> = target_dirs
-                .iter()
-                .map(|path| path.to_str().unwrap().to_owned())
-                .collect();
-
-            let msg_string = my_utils::vec_to_string("", &target_iter);
-
-            println!("got target dir[s] {}", &msg_string);
-        }
+        log_target_dirs(&target_dirs);
 
         let git_url: Option<String> = if let Some(conf) = &config {
             if conf.git_url.is_some() {
@@ -157,22 +294,200 @@ impl MainApp {
             gurl
         };
 
+        // cli first then config then abort
+        let on_conflict: ConflictPolicy =
+            if let Some(policy) = args.value_of("on_conflict") {
+                policy.parse()?
+            } else if let Some(conf) = &config {
+                conf.on_conflict.clone()
+            } else {
+                ConflictPolicy::Abort
+            };
+
+        // a target given on the cli still gets the vars of this machine
+        let template_vars = make_template_vars(
+            config.as_ref().and_then(|conf| machine_target(conf).ok()),
+        );
+
+        let link_style: LinkStyle = match &config {
+            Some(conf) => conf.link_style.clone(),
+            None => LinkStyle::Absolute,
+        };
+
+        let copy_rule = match &config {
+            Some(conf) => CopyRule::new(&under_dir, &conf.copy),
+            None => CopyRule::default(),
+        };
+
+        let write_back = args.is_present("write_back");
+        let untracked = args.is_present("untracked");
+
+        // from where manage was run, the dir is changed before linking
+        let current_dir = env::current_dir()?;
+        let to_add: Vec<PathBuf> = match args.values_of("paths") {
+            Some(paths) => paths.map(|path| current_dir.join(path)).collect(),
+            None => vec![],
+        };
+
+        let ignore = match &config {
+            Some(conf) => IgnoreRules::new(&under_dir, &conf.ignore)?,
+            None => IgnoreRules::new(&under_dir, &[])?,
+        };
+
+        let hooks = match &config {
+            Some(conf) => conf.hooks.clone(),
+            None => PackageHooks::new(),
+        };
+
+        let no_folding = match &config {
+            Some(conf) => conf.no_folding.clone(),
+            None => vec![],
+        };
+
+        let roots = match &config {
+            Some(conf) => DestRoots::new(
+                &upper_dir,
+                &conf.destinations,
+                xdg_roots(&upper_dir),
+            ),
+            None => DestRoots::new(
+                &upper_dir,
+                &Default::default(),
+                xdg_roots(&upper_dir),
+            ),
+        };
+
+        let secret_key_file =
+            match config.as_ref().and_then(|conf| conf.secret_key.clone()) {
+                Some(secret_key) => secret_key,
+                None => default_key_file()?,
+            };
+
+        let state_dir = get_xdg_state_path()?;
+        let backup_dir = make_backup_dir(&state_dir.join("backup"));
+        let manifest_file = state_dir.join("manifest.toml");
+
         Ok(MainApp {
             under_dir,
             upper_dir,
             target_dirs,
-            verbose_num,
             dry_run,
+            format,
+            output: Output::default(),
             action,
             git_url,
+            on_conflict,
+            link_style,
+            backup_dir,
+            manifest_file,
+            template_vars,
+            copy_rule,
+            write_back,
+            untracked,
+            hooks,
+            ignore,
+            no_folding,
+            roots,
+            secret_key_file,
+            to_add,
         })
     }
 
-    pub fn verbose_ouput(&self, message: &str, more: Option<&str>) {
-        if self.verbose_num == 1 && !message.is_empty() {
-            println!("{}", message);
-        } else if self.verbose_num == 2 {
-            println!("{}{}", message, more.unwrap_or(""));
+    // the packages for this machine, picked after the pull so packages and
+    // keyfile entries that came with it are linked in the same run
+    pub fn select_target_dirs(&mut self, conf: &Conf) -> Result<(), Error> {
+        self.target_dirs = filter_target_dirs(&self.under_dir, conf)?;
+        self.template_vars = make_template_vars(machine_target(conf).ok());
+
+        log_target_dirs(&self.target_dirs);
+
+        Ok(())
+    }
+
+    // the package can have its dirs linked whole
+    pub fn folds(&self, package: &str) -> bool {
+        !self
+            .no_folding
+            .iter()
+            .any(|pattern| glob_match(pattern, package).unwrap_or(false))
+    }
+
+    // a MainApp with every path under tmp for the tests
+    #[cfg(test)]
+    pub fn fake(tmp: &Path, action: Action) -> Self {
+        MainApp {
+            under_dir: tmp.join(".under"),
+            upper_dir: tmp.to_owned(),
+            target_dirs: vec![],
+            dry_run: false,
+            format: OutputFormat::Text,
+            output: Output::default(),
+            action,
+            git_url: None,
+            on_conflict: ConflictPolicy::Abort,
+            link_style: LinkStyle::Absolute,
+            backup_dir: tmp.join(".backup/100"),
+            manifest_file: tmp.join(".state/manifest.toml"),
+            template_vars: TemplateVars::new(),
+            copy_rule: CopyRule::default(),
+            write_back: false,
+            untracked: false,
+            hooks: PackageHooks::new(),
+            ignore: IgnoreRules::default(),
+            no_folding: vec![],
+            roots: DestRoots::new(tmp, &Default::default(), Default::default()),
+            secret_key_file: tmp.join(".keys/secret.key"),
+            to_add: vec![],
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use clap::{load_yaml, App};
+
+    use super::*;
+
+    fn action_of(cli_args: &[&str]) -> Action {
+        let yml = load_yaml!("cli.yml");
+        let args = App::from_yaml(yml).get_matches_from(cli_args);
+
+        Action::from_args(&args)
+    }
+
+    #[test]
+    fn test_action_from_args() {
+        assert_eq!(action_of(&["manage", "link"]), Action::Make);
+        assert_eq!(action_of(&["manage"]), Action::Make);
+        assert_eq!(action_of(&["manage", "-d"]), Action::Delete);
+        assert_eq!(action_of(&["manage", "unlink"]), Action::Delete);
+        assert_eq!(action_of(&["manage", "-r"]), Action::Remake);
+        assert_eq!(action_of(&["manage", "--check-config"]), Action::Doctor);
+        assert_eq!(
+            action_of(&["manage", "edit-secret", "netrc.enc"]),
+            Action::EditSecret
+        );
+
+        // only these need the packages of this machine picked
+        assert!(Action::Status.uses_packages());
+        assert!(!Action::Sync.uses_packages());
+        assert!(!Action::Restore.uses_packages());
+
+        // global args work before or after the subcommand
+        let yml = load_yaml!("cli.yml");
+        let args = App::from_yaml(yml).get_matches_from([
+            "manage",
+            "-v",
+            "relink",
+            "--on-conflict",
+            "skip",
+            "-D",
+        ]);
+        let sub_args = action_args(&args);
+
+        assert_eq!(Action::from_args(&args), Action::Remake);
+        assert_eq!(sub_args.occurrences_of("verbose"), 1);
+        assert!(sub_args.is_present("dryrun"));
+        assert_eq!(sub_args.value_of("on_conflict"), Some("skip"));
+    }
+}
@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::symbolic_link::link_points_to;
+
+// one link made by manage
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ManifestEntry {
+    pub link: PathBuf,
+    pub target: PathBuf,
+    pub package: String,
+    pub commit: String,
+    // what was copied when the file is a copy and not a link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+impl ManifestEntry {
+    // the link is still there and still points where we left it, a copy is
+    // intact while it is a real file edited or not
+    pub fn is_intact(&self) -> bool {
+        match self.checksum {
+            Some(_) => self
+                .link
+                .symlink_metadata()
+                .map(|meta| meta.file_type().is_file())
+                .unwrap_or(false),
+            None => link_points_to(&self.link, &self.target),
+        }
+    }
+}
+
+// every link manage has made on this machine, kept in the state dir so
+// delete and remake can find links for files no longer in under_dir
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    // dir links turned in to real dirs so a second package could add to
+    // them, only these are folded back
+    #[serde(default)]
+    pub unfolded: Vec<PathBuf>,
+    #[serde(default)]
+    pub links: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    // a missing manifest is an empty one
+    pub fn load(manifest_file: &Path) -> Result<Self, Box<dyn Error>> {
+        if !manifest_file.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let toml_string = fs::read_to_string(manifest_file)?;
+
+        toml::from_str(&toml_string).map_err(|err| {
+            Box::from(format!("bad manifest {:?} {}", manifest_file, err))
+        })
+    }
+
+    // write next to the old manifest then rename over it
+    pub fn save(&self, manifest_file: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = manifest_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let toml_string = toml::to_string(self)?;
+        let tmp_file = manifest_file.with_extension("toml.tmp");
+
+        fs::write(&tmp_file, toml_string)?;
+        fs::rename(&tmp_file, manifest_file)?;
+
+        Ok(())
+    }
+
+    // replace any entry for the same link
+    pub fn record(&mut self, entry: ManifestEntry) {
+        self.remove(&entry.link);
+        self.links.push(entry);
+    }
+
+    pub fn remove(&mut self, link: &Path) {
+        self.links.retain(|entry| entry.link != link);
+    }
+
+    pub fn contains(&self, link: &Path) -> bool {
+        self.links.iter().any(|entry| entry.link == link)
+    }
+
+    pub fn record_unfolded(&mut self, dir: &Path) {
+        if !self.unfolded.iter().any(|unfolded| unfolded == dir) {
+            self.unfolded.push(dir.to_owned());
+        }
+    }
+
+    pub fn remove_unfolded(&mut self, dir: &Path) {
+        self.unfolded.retain(|unfolded| unfolded != dir);
+    }
+
+    // the checksum recorded when the file was copied
+    pub fn checksum(&self, link: &Path) -> Option<String> {
+        self.links
+            .iter()
+            .find(|entry| entry.link == link)
+            .and_then(|entry| entry.checksum.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn fake_entry(link: &str, commit: &str) -> ManifestEntry {
+        ManifestEntry {
+            link: PathBuf::from(link),
+            target: PathBuf::from("/under/zsh_all/zshrc"),
+            package: "zsh_all".to_string(),
+            commit: commit.to_string(),
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn test_manifest_save_load() {
+        let temp_path = tempdir().unwrap().into_path();
+        let manifest_file = temp_path.join("state/manifest.toml");
+
+        match Manifest::load(&manifest_file) {
+            Ok(manifest) => assert!(manifest.links.is_empty()),
+            Err(err) => panic!("cant load missing manifest {}", err),
+        }
+
+        let mut manifest = Manifest::default();
+        manifest.record(fake_entry("/home/.zshrc", "abc"));
+        manifest.record(fake_entry("/home/.zshenv", "abc"));
+        manifest.record_unfolded(Path::new("/home/.config/i3"));
+
+        if let Err(err) = manifest.save(&manifest_file) {
+            panic!("cant save manifest {}", err);
+        }
+
+        match Manifest::load(&manifest_file) {
+            Ok(loaded) => {
+                assert_eq!(loaded.links, manifest.links);
+                assert_eq!(loaded.unfolded, manifest.unfolded);
+            }
+            Err(err) => panic!("cant load manifest {}", err),
+        }
+    }
+
+    #[test]
+    fn test_manifest_record_replaces() {
+        let mut manifest = Manifest::default();
+        manifest.record(fake_entry("/home/.zshrc", "abc"));
+        manifest.record(fake_entry("/home/.zshrc", "def"));
+
+        assert_eq!(manifest.links, vec![fake_entry("/home/.zshrc", "def")]);
+
+        manifest.remove(Path::new("/home/.zshrc"));
+        assert!(!manifest.contains(Path::new("/home/.zshrc")));
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use log::{error, log_enabled, Level};
+
+use crate::{
+    copy_utils::is_deployed,
+    error::Error as ManageError,
+    link_manifest::Manifest,
+    output_utils::{LinkRecord, OutputFormat},
+    symbolic_link::{get_status_vec, resolve_link, LinkKind, SymLink},
+    MainApp,
+};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum LinkState {
+    Linked,
+    Missing,
+    Conflict,
+    NotOwned,
+    Dangling,
+    // a rendered or copied file that no longer matches under_dir
+    Changed,
+    // skipped by an ignore pattern
+    Ignored,
+    // a template or secret that cant be rendered, like a secret without
+    // its key
+    Unrendered,
+}
+
+impl fmt::Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state_str = match self {
+            LinkState::Linked => "linked",
+            LinkState::Missing => "missing",
+            LinkState::Conflict => "conflicting file",
+            LinkState::NotOwned => "not owned",
+            LinkState::Dangling => "dangling",
+            LinkState::Changed => "changed",
+            LinkState::Ignored => "ignored",
+            LinkState::Unrendered => "cant render",
+        };
+
+        write!(f, "{}", state_str)
+    }
+}
+
+impl LinkState {
+    // nothing to do for the path, ignored paths are left alone on purpose
+    pub fn is_linked(&self) -> bool {
+        matches!(self, LinkState::Linked | LinkState::Ignored)
+    }
+
+    // one word for the json output
+    pub fn key(&self) -> &'static str {
+        match self {
+            LinkState::Linked => "linked",
+            LinkState::Missing => "missing",
+            LinkState::Conflict => "conflict",
+            LinkState::NotOwned => "not_owned",
+            LinkState::Dangling => "dangling",
+            LinkState::Changed => "changed",
+            LinkState::Ignored => "ignored",
+            LinkState::Unrendered => "unrendered",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PathStatus {
+    pub upper_file: PathBuf,
+    pub target_file: PathBuf,
+    pub state: LinkState,
+    // why the path could not be rendered
+    pub error: Option<String>,
+}
+
+impl PathStatus {
+    fn new(sym: &SymLink, state: LinkState) -> Self {
+        PathStatus {
+            upper_file: sym.upper_file.clone(),
+            target_file: sym.target_file.clone(),
+            state,
+            error: None,
+        }
+    }
+}
+
+impl fmt::Display for PathStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} -> {}",
+            self.state,
+            self.upper_file.display(),
+            self.target_file.display()
+        )?;
+
+        match &self.error {
+            Some(err) => write!(f, ", {}", err),
+            None => Ok(()),
+        }
+    }
+}
+
+// the state of one path link found, rendered files are rendered again so a
+// missing key is only a problem for the secrets it is needed for
+fn sym_status(sym: &SymLink, main_app: &MainApp) -> PathStatus {
+    let state = match sym.kind {
+        LinkKind::Ignored => LinkState::Ignored,
+        _ if sym.conflict => match sym.upper_file.read_link() {
+            Ok(link_dest)
+                if !resolve_link(&sym.upper_file, &link_dest).exists() =>
+            {
+                LinkState::Dangling
+            }
+            Ok(_) => LinkState::NotOwned,
+            Err(_) => LinkState::Conflict,
+        },
+        _ if !sym.exists => LinkState::Missing,
+        LinkKind::Copy if is_deployed(&sym.upper_file, &sym.target_file) => {
+            LinkState::Linked
+        }
+        LinkKind::Copy => LinkState::Changed,
+        LinkKind::Template | LinkKind::Secret => {
+            match sym.is_rendered(main_app) {
+                Ok(true) => LinkState::Linked,
+                Ok(false) => LinkState::Changed,
+                Err(err) => {
+                    return PathStatus {
+                        error: Some(err.to_string()),
+                        ..PathStatus::new(sym, LinkState::Unrendered)
+                    }
+                }
+            }
+        }
+        LinkKind::Link | LinkKind::Unfold => LinkState::Linked,
+    };
+
+    PathStatus::new(sym, state)
+}
+
+// every path of the package in the state link would find it in, with the
+// links the manifest has whose file is gone from under_dir
+pub fn package_status(
+    main_app: &MainApp,
+    target_dir: &Path,
+    manifest: &Manifest,
+) -> Result<Vec<PathStatus>, Box<dyn Error>> {
+    let mut to_ret: Vec<PathStatus> =
+        get_status_vec(main_app, target_dir, manifest)?
+            .iter()
+            // the dir link belongs to the other package
+            .filter(|sym| sym.kind != LinkKind::Unfold)
+            .map(|sym| sym_status(sym, main_app))
+            .collect();
+
+    let package = target_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    for entry in &manifest.links {
+        let dangling = entry.package == package
+            && entry.checksum.is_none()
+            && entry.is_intact()
+            && !entry.target.exists();
+
+        if dangling
+            && !to_ret.iter().any(|status| status.upper_file == entry.link)
+        {
+            to_ret.push(PathStatus {
+                upper_file: entry.link.clone(),
+                target_file: entry.target.clone(),
+                state: LinkState::Dangling,
+                error: None,
+            });
+        }
+    }
+
+    Ok(to_ret)
+}
+
+// count each state like "2 linked, 1 missing"
+fn count_states(statuses: &[PathStatus]) -> String {
+    let mut counts: BTreeMap<LinkState, usize> = BTreeMap::new();
+
+    for status in statuses {
+        *counts.entry(status.state).or_insert(0) += 1;
+    }
+
+    counts
+        .iter()
+        .map(|(state, count)| format!("{} {}", count, state))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// write the status of every package as the output of the command, any path
+// that is not linked or package that cant be looked at is an error so
+// scripts get a non zero exit code
+pub fn print_status(
+    main_app: &MainApp,
+    manifest: &Manifest,
+) -> Result<(), Box<dyn Error>> {
+    let mut not_linked = 0;
+    let mut failed: Option<Box<dyn Error>> = None;
+
+    for target_dir in &main_app.target_dirs {
+        let package = target_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let statuses = match package_status(main_app, target_dir, manifest) {
+            Ok(statuses) => statuses,
+            Err(err) => {
+                error!("Status Error {} {}", package, err);
+                failed.get_or_insert(err);
+                continue;
+            }
+        };
+
+        not_linked += statuses
+            .iter()
+            .filter(|status| !status.state.is_linked())
+            .count();
+
+        // every path is listed so a script does not need -v
+        if main_app.format == OutputFormat::Json {
+            for status in &statuses {
+                main_app.output.record(&LinkRecord {
+                    event: "status",
+                    upper: &status.upper_file,
+                    target: &status.target_file,
+                    package: &package,
+                    action: "status",
+                    result: status.state.key(),
+                    error: status.error.clone(),
+                });
+            }
+
+            continue;
+        }
+
+        main_app.output.line(&format!(
+            "{}: {}",
+            package,
+            count_states(&statuses)
+        ));
+
+        // linked and ignored paths are only listed with -v
+        for status in &statuses {
+            if !status.state.is_linked() || log_enabled!(Level::Debug) {
+                main_app.output.line(&format!("    {}", status));
+            }
+        }
+    }
+
+    match failed {
+        Some(err) => Err(err),
+        None if not_linked > 0 => {
+            Err(Box::from(ManageError::NotLinked { count: not_linked }))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::os::unix;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::copy_utils::file_checksum;
+    use crate::ignore_utils::IgnoreRules;
+    use crate::link_manifest::ManifestEntry;
+    use crate::output_utils::Output;
+    use crate::Action;
+
+    #[test]
+    fn test_package_status() {
+        let temp_path = tempdir().unwrap().into_path().canonicalize().unwrap();
+        let upper_dir = temp_path.clone();
+        let target_dir = temp_path.join(".under/fake_zsh");
+
+        fs::create_dir_all(target_dir.join("zshrc.d")).unwrap();
+        fs::create_dir_all(upper_dir.join("zshrc.d")).unwrap();
+
+        for file in &[
+            "zshrc",
+            "zshenv",
+            "zprofile",
+            "zlogin",
+            "zshrc.d/alias",
+            "README.md",
+            "netrc.enc",
+        ] {
+            fs::write(target_dir.join(file), "").unwrap();
+        }
+
+        fs::write(temp_path.join("other"), "").unwrap();
+
+        unix::fs::symlink(target_dir.join("zshrc"), upper_dir.join("zshrc"))
+            .unwrap();
+        fs::write(upper_dir.join("zshenv"), "real").unwrap();
+        unix::fs::symlink(temp_path.join("other"), upper_dir.join("zprofile"))
+            .unwrap();
+        unix::fs::symlink(temp_path.join("gone"), upper_dir.join("zlogin"))
+            .unwrap();
+        fs::write(upper_dir.join("netrc"), "old").unwrap();
+
+        // linked once then its file was taken out of the package
+        unix::fs::symlink(
+            target_dir.join("zlogout"),
+            upper_dir.join("zlogout"),
+        )
+        .unwrap();
+
+        let mut main_app = MainApp::fake(&temp_path, Action::Status);
+        main_app.ignore = IgnoreRules::new(
+            &temp_path.join(".under"),
+            &["README*".to_string()],
+        )
+        .unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.record(ManifestEntry {
+            link: upper_dir.join("netrc"),
+            target: target_dir.join("netrc.enc"),
+            package: "fake_zsh".to_string(),
+            commit: String::new(),
+            checksum: Some(file_checksum(&upper_dir.join("netrc")).unwrap()),
+        });
+        manifest.record(ManifestEntry {
+            link: upper_dir.join("zlogout"),
+            target: target_dir.join("zlogout"),
+            package: "fake_zsh".to_string(),
+            commit: String::new(),
+            checksum: None,
+        });
+
+        let statuses = match package_status(&main_app, &target_dir, &manifest) {
+            Ok(statuses) => statuses,
+            Err(err) => panic!("cant get status {}", err),
+        };
+
+        let status_of = |name: &str| {
+            statuses
+                .iter()
+                .find(|status| status.upper_file == upper_dir.join(name))
+        };
+        let state_of = |name: &str| status_of(name).map(|status| status.state);
+
+        assert_eq!(statuses.len(), 8);
+        assert_eq!(state_of("zshrc"), Some(LinkState::Linked));
+        assert_eq!(state_of("zshenv"), Some(LinkState::Conflict));
+        assert_eq!(state_of("zprofile"), Some(LinkState::NotOwned));
+        assert_eq!(state_of("zlogin"), Some(LinkState::Dangling));
+        assert_eq!(state_of("zshrc.d/alias"), Some(LinkState::Missing));
+        assert_eq!(state_of("README.md"), Some(LinkState::Ignored));
+        assert_eq!(state_of("zlogout"), Some(LinkState::Dangling));
+
+        // no key only stops the secret
+        assert_eq!(state_of("netrc"), Some(LinkState::Unrendered));
+        assert!(status_of("netrc").unwrap().error.is_some());
+    }
+
+    #[test]
+    fn test_print_status() {
+        let temp_path = tempdir().unwrap().into_path().canonicalize().unwrap();
+        let target_dir = temp_path.join(".under/fake_zsh");
+
+        fs::create_dir_all(&target_dir).unwrap();
+        fs::write(target_dir.join("zshrc"), "").unwrap();
+        fs::write(target_dir.join("zshenv"), "").unwrap();
+        unix::fs::symlink(target_dir.join("zshrc"), temp_path.join("zshrc"))
+            .unwrap();
+
+        let out_file = temp_path.join("status.txt");
+
+        let mut main_app = MainApp::fake(&temp_path, Action::Status);
+        main_app.target_dirs = vec![target_dir];
+        main_app.output = Output::new(File::create(&out_file).unwrap());
+
+        match print_status(&main_app, &Manifest::default()) {
+            Ok(_) => panic!("missing path was not an error"),
+            Err(err) => assert!(
+                matches!(
+                    ManageError::from(err),
+                    ManageError::NotLinked { count: 1 }
+                ),
+                "not a status error"
+            ),
+        }
+
+        // the report is output and not a log line so -q keeps it
+        let report = fs::read_to_string(&out_file).unwrap();
+        assert!(
+            report.starts_with("fake_zsh: 1 linked, 1 missing\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("    missing "), "{}", report);
+        assert!(!report.contains("    linked "), "{}", report);
+    }
+}
@@ -0,0 +1,225 @@
+use std::error::Error;
+use std::fs;
+use std::os::unix;
+use std::path::{Path, PathBuf};
+
+use crate::backup_utils::move_path;
+use crate::error::Error as ManageError;
+
+// one change made to upper_dir and what it takes to undo it
+#[derive(Debug)]
+enum Change {
+    // a link or file made where there was nothing
+    Made(PathBuf),
+    // a dir made to hold a copied file
+    MadeDir(PathBuf),
+    // an empty dir removed so a link could take its place
+    RemovedDir(PathBuf),
+    // a link removed, dest is where it pointed
+    Removed {
+        link: PathBuf,
+        dest: PathBuf,
+    },
+    // a link pointed somewhere new
+    Replaced {
+        link: PathBuf,
+        old_dest: PathBuf,
+    },
+    // a real file moved out of the way, when keep is false it is only moved
+    // so it can be put back and gets removed on commit
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+        keep: bool,
+    },
+}
+
+fn remove_path(path: &Path) -> Result<(), Box<dyn Error>> {
+    let removed = if path.symlink_metadata()?.file_type().is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    removed.map_err(|err| Box::from(format!("cant remove {:?} {}", path, err)))
+}
+
+impl Change {
+    fn undo(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Change::Made(link) => fs::remove_file(link)?,
+            Change::MadeDir(dir) => fs::remove_dir(dir)?,
+            Change::RemovedDir(dir) => fs::create_dir(dir)?,
+            Change::Removed { link, dest } => unix::fs::symlink(dest, link)?,
+            Change::Replaced { link, old_dest } => {
+                fs::remove_file(link)?;
+                unix::fs::symlink(old_dest, link)?;
+            }
+            Change::Moved { from, to, .. } => move_path(to, from)?,
+        }
+
+        Ok(())
+    }
+}
+
+// every change made while applying a target dir so a failure part way
+// through can put upper_dir back how it was
+#[derive(Default, Debug)]
+pub struct Transaction {
+    changes: Vec<Change>,
+}
+
+impl Transaction {
+    pub fn made(&mut self, link: &Path) {
+        self.changes.push(Change::Made(link.to_owned()));
+    }
+
+    pub fn made_dir(&mut self, dir: &Path) {
+        self.changes.push(Change::MadeDir(dir.to_owned()));
+    }
+
+    pub fn removed_dir(&mut self, dir: &Path) {
+        self.changes.push(Change::RemovedDir(dir.to_owned()));
+    }
+
+    pub fn removed(&mut self, link: &Path, dest: &Path) {
+        self.changes.push(Change::Removed {
+            link: link.to_owned(),
+            dest: dest.to_owned(),
+        });
+    }
+
+    pub fn replaced(&mut self, link: &Path, old_dest: &Path) {
+        self.changes.push(Change::Replaced {
+            link: link.to_owned(),
+            old_dest: old_dest.to_owned(),
+        });
+    }
+
+    pub fn moved(&mut self, from: &Path, to: &Path, keep: bool) {
+        self.changes.push(Change::Moved {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            keep,
+        });
+    }
+
+    // everything worked so drop the files only kept for a rollback
+    pub fn commit(self) -> Result<(), Box<dyn Error>> {
+        for change in &self.changes {
+            if let Change::Moved {
+                to, keep: false, ..
+            } = change
+            {
+                remove_path(to)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // undo newest first and keep going past errors so as much as possible
+    // is put back
+    pub fn rollback(self) -> Result<usize, Box<dyn Error>> {
+        let mut failed: Vec<String> = vec![];
+
+        for change in self.changes.iter().rev() {
+            if let Err(err) = change.undo() {
+                failed.push(format!("{:?} {}", change, err));
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(self.changes.len())
+        } else {
+            Err(Box::from(failed.join("\n")))
+        }
+    }
+}
+
+// add how the rollback went to the error that caused it
+pub fn rollback_error(
+    err: Box<dyn Error>,
+    trans: Transaction,
+) -> Box<dyn Error> {
+    let message = match trans.rollback() {
+        Ok(0) => return err,
+        Ok(count) => format!("rolled back {} change[s]", count),
+        Err(roll_err) => format!("rollback failed\n{}", roll_err),
+    };
+
+    // keep the kind of the error that caused it
+    Box::from(ManageError::RolledBack {
+        source: Box::new(ManageError::from(err)),
+        message,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_transaction_rollback() {
+        let temp_path = tempdir().unwrap().into_path();
+
+        let real_file = temp_path.join("zshrc");
+        let stash_file = temp_path.join(".zshrc.manage_old");
+        let made_link = temp_path.join("made");
+        let removed_link = temp_path.join("removed");
+        let replaced_link = temp_path.join("replaced");
+
+        fs::write(&real_file, "real").unwrap();
+        unix::fs::symlink("/old", &replaced_link).unwrap();
+
+        let mut trans = Transaction::default();
+
+        move_path(&real_file, &stash_file).unwrap();
+        trans.moved(&real_file, &stash_file, false);
+
+        unix::fs::symlink("/new", &made_link).unwrap();
+        trans.made(&made_link);
+
+        trans.removed(&removed_link, Path::new("/gone"));
+
+        fs::remove_file(&replaced_link).unwrap();
+        unix::fs::symlink("/new", &replaced_link).unwrap();
+        trans.replaced(&replaced_link, Path::new("/old"));
+
+        match trans.rollback() {
+            Ok(count) => assert_eq!(count, 4),
+            Err(err) => panic!("cant roll back {}", err),
+        }
+
+        assert_eq!(fs::read_to_string(&real_file).unwrap(), "real");
+        assert!(stash_file.symlink_metadata().is_err(), "stash left");
+        assert!(made_link.symlink_metadata().is_err(), "made link left");
+        assert_eq!(removed_link.read_link().unwrap(), Path::new("/gone"));
+        assert_eq!(replaced_link.read_link().unwrap(), Path::new("/old"));
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let temp_path = tempdir().unwrap().into_path();
+
+        let stash_file = temp_path.join(".zshrc.manage_old");
+        let backup_file = temp_path.join("backup/zshrc");
+
+        fs::write(&stash_file, "stash").unwrap();
+        fs::create_dir_all(backup_file.parent().unwrap()).unwrap();
+        fs::write(&backup_file, "backup").unwrap();
+
+        let mut trans = Transaction::default();
+        trans.moved(&temp_path.join("zshrc"), &stash_file, false);
+        trans.moved(&temp_path.join("zshrc"), &backup_file, true);
+
+        if let Err(err) = trans.commit() {
+            panic!("cant commit {}", err);
+        }
+
+        assert!(!stash_file.exists(), "stash left after commit");
+        assert!(backup_file.exists(), "backup removed on commit");
+    }
+}
@@ -0,0 +1,196 @@
+use std::cmp;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::output_utils::OutputFormat;
+
+// kept in the state dir next to the manifest
+pub const LOG_FILE: &str = "manage.log";
+
+// info by default, each -v shows more and each -q shows less
+pub fn log_level(verbose: u64, quiet: u64) -> LevelFilter {
+    match (verbose, quiet) {
+        (_, 1) => LevelFilter::Warn,
+        (_, q) if q > 1 => LevelFilter::Error,
+        (0, _) => LevelFilter::Info,
+        (1, _) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+// info and below go to stdout, warnings and errors to stderr, the log file
+// gets every record with its time and level whatever the terminal shows
+pub struct Logger {
+    level: LevelFilter,
+    file: Option<Mutex<File>>,
+    // stdout only holds the json records, all text goes to stderr
+    json: bool,
+}
+
+impl Logger {
+    pub fn new(
+        level: LevelFilter,
+        log_file: Option<&Path>,
+        format: OutputFormat,
+    ) -> Result<Self, Box<dyn Error>> {
+        let file = match log_file {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| {
+                        format!("cant open log file {:?} {}", path, err)
+                    })?;
+
+                Some(Mutex::new(file))
+            }
+            None => None,
+        };
+
+        Ok(Logger {
+            level,
+            file,
+            json: format == OutputFormat::Json,
+        })
+    }
+
+    fn file_level(&self) -> LevelFilter {
+        match self.file {
+            Some(_) => LevelFilter::Trace,
+            None => LevelFilter::Off,
+        }
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        cmp::max(self.level, self.file_level())
+    }
+
+    // there is only one logger so this can only be done once
+    pub fn init(self) -> Result<(), Box<dyn Error>> {
+        log::set_max_level(self.max_level());
+
+        log::set_boxed_logger(Box::new(self))
+            .map_err(|err| Box::from(format!("cant start logging {}", err)))
+    }
+}
+
+impl Log for Logger {
+    // only our own records, not those of the crates we use
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("manage")
+            && metadata.level() <= self.max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.level() <= self.level {
+            match record.level() {
+                Level::Error | Level::Warn => eprintln!("{}", record.args()),
+                _ if self.json => eprintln!("{}", record.args()),
+                _ => println!("{}", record.args()),
+            }
+        }
+
+        if let Some(file) = &self.file {
+            if record.level() <= self.file_level() {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs())
+                    .unwrap_or_default();
+
+                // every line of a list gets its own time and level
+                let message = record.args().to_string();
+
+                if let Ok(mut file) = file.lock() {
+                    for line in message.lines() {
+                        // a log that cant be written should not stop a run
+                        let _ = writeln!(
+                            file,
+                            "{} {} {}",
+                            secs,
+                            record.level(),
+                            line
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(Ok(mut file)) = self.file.as_ref().map(|file| file.lock()) {
+            let _ = file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_logger_file() {
+        assert_eq!(log_level(0, 0), LevelFilter::Info);
+        assert_eq!(log_level(2, 0), LevelFilter::Trace);
+        assert_eq!(log_level(0, 1), LevelFilter::Warn);
+
+        let temp_path = tempdir().unwrap().into_path();
+        let log_file = temp_path.join("state/manage.log");
+
+        let logger = match Logger::new(
+            LevelFilter::Warn,
+            Some(&log_file),
+            OutputFormat::Text,
+        ) {
+            Ok(logger) => logger,
+            Err(err) => panic!("cant make logger {}", err),
+        };
+
+        for (level, message) in [
+            (Level::Info, "made link"),
+            (Level::Debug, "got config"),
+            (Level::Warn, "skipping existing file"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target("manage::symbolic_link")
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+
+        // records from other crates are left out
+        logger.log(
+            &Record::builder()
+                .level(Level::Error)
+                .target("git2")
+                .args(format_args!("not ours"))
+                .build(),
+        );
+
+        let logged = fs::read_to_string(&log_file).unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+
+        assert_eq!(lines.len(), 3, "{}", logged);
+        assert!(lines[0].ends_with("INFO made link"));
+        assert!(lines[1].ends_with("DEBUG got config"));
+        assert!(lines[2].ends_with("WARN skipping existing file"));
+    }
+}
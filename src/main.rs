@@ -1,52 +1,199 @@
-/// get the user config
-///
-/// make sure the git repo hase been clone
-/// and both server and local copy are up to date
-///
-/// then make all the needed symlinks
+//! get the user config
+//!
+//! make sure the git repo hase been clone
+//! and both server and local copy are up to date
+//!
+//! then make all the needed symlinks
 
 #[macro_use]
 extern crate clap;
 use clap::App;
+use log::{debug, error, info, warn, LevelFilter};
 
-use std::error::Error;
+use std::cmp;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use manage::{
+    action_args,
+    config_check::{check_config, check_setup},
     git_utils::manage_git,
-    my_utils::make_then_check_path,
+    init_utils::{init_machine, InitOptions},
+    log_utils::{log_level, Logger, LOG_FILE},
+    my_utils::{get_xdg_config_path, get_xdg_state_path, make_then_check_path},
+    output_utils::{Output, OutputFormat},
+    secret_utils::{default_key_file, edit_secret, encrypt_file},
     symbolic_link::manage_symlinks,
     user_config::{get_xdg_user_config_path, make_config},
-    MainApp,
+    Action, Error, MainApp,
 };
 
 // so we can use ?
-fn run() -> Result<(), Box<dyn Error>> {
+fn run() -> Result<(), Error> {
     let yml = load_yaml!("cli.yml");
     let arg_matches = App::from_yaml(yml).get_matches();
 
+    let action = Action::from_args(&arg_matches);
+    let args = action_args(&arg_matches);
+
+    let log_file = if args.is_present("log") {
+        Some(get_xdg_state_path()?.join(LOG_FILE))
+    } else {
+        None
+    };
+
+    let format: OutputFormat = match args.value_of("format") {
+        Some(format) => format.parse()?,
+        None => OutputFormat::Text,
+    };
+
+    let verbose = args.occurrences_of("verbose");
+    let level = match log_level(verbose, args.occurrences_of("quiet")) {
+        // the json has what the text would say, only show it when asked
+        level if format == OutputFormat::Json && verbose == 0 => {
+            cmp::min(level, LevelFilter::Warn)
+        }
+        level => level,
+    };
+
+    Logger::new(level, log_file.as_deref(), format)?.init()?;
+
+    if action == Action::Init {
+        // the config is not there yet so it can not be checked
+        let config_path = match args.value_of("config") {
+            Some(path) => PathBuf::from(path),
+            None => get_xdg_config_path()?,
+        };
+
+        init_machine(&InitOptions {
+            git_url: args.value_of("url").ok_or("need a git url")?,
+            under_dir: args.value_of("under_dir").unwrap_or(".dots"),
+            config_path: &config_path,
+            force: args.is_present("force"),
+            dry_run: args.is_present("dryrun"),
+        })?;
+
+        if !args.is_present("link") || args.is_present("dryrun") {
+            return Ok(());
+        }
+    }
+
     // TODO: make it possible to run without config
-    let config_path = if let Some(path) = arg_matches.value_of("config") {
-        make_then_check_path(&[path]).ok_or("bad config from cli")?
+    let config_path = if let Some(path) = args.value_of("config") {
+        make_then_check_path(&[path]).ok_or_else(|| Error::Config {
+            path: Some(PathBuf::from(path)),
+            message: "bad config from cli".to_string(),
+        })?
     } else {
         get_xdg_user_config_path()?
     };
 
+    if action == Action::Doctor {
+        let mut problems: Vec<String> = check_config(&config_path)
+            .map_err(|err| {
+                Error::or_kind(err, |message| Error::Config {
+                    path: Some(config_path.clone()),
+                    message,
+                })
+            })?
+            .iter()
+            .map(|problem| format!("{}:{}", config_path.display(), problem))
+            .collect();
+
+        // the setup can only be checked once the config makes sense
+        if problems.is_empty() {
+            problems.append(&mut check_setup(&make_config(&config_path)?));
+        }
+
+        for problem in &problems {
+            warn!("{}", problem);
+        }
+
+        if !problems.is_empty() {
+            return Err(Error::Config {
+                path: Some(config_path),
+                message: format!("{} problem[s]", problems.len()),
+            });
+        }
+
+        info!("config ok");
+        return Ok(());
+    }
+
     // get run time options from config file
     let config = make_config(&config_path);
-    let main = MainApp::new(arg_matches, config)?;
 
-    main.verbose_ouput("got main app", None);
+    if action == Action::Encrypt || action == Action::EditSecret {
+        let key_file = match config.ok().and_then(|conf| conf.secret_key) {
+            Some(key_file) => key_file,
+            None => default_key_file()?,
+        };
+
+        // the old flags hold their paths them selves
+        if action == Action::Encrypt {
+            let paths: Vec<&str> = match arg_matches.values_of("encrypt") {
+                Some(paths) => paths.collect(),
+                None => [args.value_of("plain"), args.value_of("secret")]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect(),
+            };
+
+            let (plain, secret) = match paths.as_slice() {
+                [plain, secret] => (*plain, *secret),
+                _ => return Err(Error::from("need a plain and a secret file")),
+            };
+
+            encrypt_file(Path::new(plain), Path::new(secret), &key_file)?;
+            info!("encrypted {} in to {}", plain, secret);
+        } else {
+            let secret = arg_matches
+                .value_of("edit_secret")
+                .or_else(|| args.value_of("secret"))
+                .ok_or("need a secret file")?;
+
+            if edit_secret(Path::new(secret), &key_file)? {
+                info!("encrypted changes in to {}", secret);
+            } else {
+                info!("no changes to {}", secret);
+            }
+        }
+
+        return Ok(());
+    }
+    let mut main = MainApp::new(arg_matches, config)?;
+
+    // the records are what manage prints, logs go to stderr
+    main.output = Output::new(io::stdout());
+
+    debug!("got main app");
 
     // make sure the git repo is cloned local and up both the server and the
     // local copy are synced
     manage_git(&main)?;
 
+    // the config is read again as it can live in the repo that was pulled,
+    // sync and restore dont need a target for this machine
+    if main.action.uses_packages() && main.target_dirs.is_empty() {
+        main.select_target_dirs(&make_config(&config_path)?)?;
+    }
+
     // make the needed symlinks
     manage_symlinks(&main)
 }
 
 fn main() {
+    // each kind of error has its own exit code for scripts
     if let Err(err) = run() {
-        eprintln!("Error: {}", err);
+        // errors from before the logger started still need to be seen
+        if log::max_level() == LevelFilter::Off {
+            eprintln!("Error: {}", err);
+        } else {
+            error!("Error: {}", err);
+        }
+
+        process::exit(err.exit_code());
     }
 }
@@ -1,21 +1,43 @@
+use std::env;
 use std::fmt;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
 // make a PathBuf joining the strs to the end
-fn str_to_path(to_add_collection: &[&str]) -> PathBuf {
+pub fn str_to_path(to_add_collection: &[&str]) -> PathBuf {
     let mut path_accu: PathBuf = PathBuf::new();
 
     for to_add in to_add_collection {
-        // TODO: find out why . in toml string fucks up the whole str
-        path_accu = path_accu.join(to_add.replace("\"", ""));
+        path_accu = path_accu.join(to_add);
     }
 
     path_accu
 }
 
+// the shortest relative path from from_dir to to, both need to be absolute
+pub fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(from_comp, to_comp)| from_comp == to_comp)
+        .count();
+
+    let mut to_ret = PathBuf::new();
+
+    for _ in common..from.len() {
+        to_ret.push("..");
+    }
+
+    for comp in &to[common..] {
+        to_ret.push(comp);
+    }
+
+    to_ret
+}
+
 // run a sanity check on a path
 fn check_path(path: PathBuf) -> Option<PathBuf> {
     if path.exists() {
@@ -31,19 +53,6 @@ pub fn make_then_check_path(strs_to_add: &[&str]) -> Option<PathBuf> {
     check_path(maybe_path)
 }
 
-pub fn open_file_or_panic(path: &PathBuf) -> String {
-    match File::open(path) {
-        Ok(mut file) => {
-            let mut buffer_string = String::new();
-            file.read_to_string(&mut buffer_string)
-                .expect("cant get file to string");
-
-            buffer_string
-        }
-        Err(err) => panic!("{}", err),
-    }
-}
-
 // TODO: this is trash and i feel bad
 pub fn get_linux_hostname() -> Option<String> {
     let h_out = Command::new("hostname")
@@ -65,3 +74,52 @@ where
         .collect::<Vec<String>>()
         .join("\n")
 }
+
+// the config file, $XDG_CONFIG_HOME/manage falling back to ~/.config
+pub fn get_xdg_config_path() -> Result<PathBuf, String> {
+    if let Ok(var) = env::var("XDG_CONFIG_HOME") {
+        if !var.is_empty() {
+            return Ok(str_to_path(&[&var, "manage"]));
+        }
+    }
+
+    match env::var("HOME") {
+        Ok(home) => Ok(str_to_path(&[&home, ".config/manage"])),
+        Err(err) => Err(format!("no XDG_CONFIG_HOME or HOME {}", err)),
+    }
+}
+
+// $XDG_STATE_HOME/manage falling back to ~/.local/state/manage
+pub fn get_xdg_state_path() -> Result<PathBuf, String> {
+    if let Ok(var) = env::var("XDG_STATE_HOME") {
+        if !var.is_empty() {
+            return Ok(str_to_path(&[&var, "manage"]));
+        }
+    }
+
+    match env::var("HOME") {
+        Ok(home) => Ok(str_to_path(&[&home, ".local/state/manage"])),
+        Err(err) => Err(format!("no XDG_STATE_HOME or HOME {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(
+                Path::new("/home/user/.config/i3"),
+                Path::new("/home/user/.under/i3/.config/i3/config")
+            ),
+            Path::new("../../.under/i3/.config/i3/config")
+        );
+
+        assert_eq!(
+            relative_path(Path::new("/home/user"), Path::new("/home/user/a")),
+            Path::new("a")
+        );
+    }
+}
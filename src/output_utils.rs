@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use log::warn;
+use serde::Serialize;
+
+// how plans, status and runs are shown
+#[derive(Default, PartialEq, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    // one json object per line for scripts
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown format {}", format)),
+        }
+    }
+}
+
+// one link that was planned, done or looked at
+#[derive(Serialize, Debug)]
+pub struct LinkRecord<'a> {
+    // plan for dry runs, done for live runs and status for status
+    pub event: &'a str,
+    pub upper: &'a Path,
+    pub target: &'a Path,
+    pub package: &'a str,
+    pub action: &'a str,
+    // planned, changed, unchanged or failed, the state for status
+    pub result: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// where the output of a command goes, main gives it stdout and a program
+// using the library its own writer, nothing is written until one is given,
+// logs never go here
+pub struct Output(RefCell<Box<dyn Write>>);
+
+impl Output {
+    pub fn new<W: Write + 'static>(writer: W) -> Self {
+        Output(RefCell::new(Box::new(writer)))
+    }
+
+    // each record is written as it happens so a live run can be followed,
+    // the log level and -q never drop one
+    pub fn record(&self, record: &LinkRecord) {
+        let written = serde_json::to_string(record)
+            .map_err(io::Error::from)
+            .and_then(|json| writeln!(self.0.borrow_mut(), "{}", json));
+
+        if let Err(err) = written {
+            warn!("cant write json for {:?} {}", record.upper, err);
+        }
+    }
+
+    // a line of a text report like status
+    pub fn line(&self, line: &str) {
+        if let Err(err) = writeln!(self.0.borrow_mut(), "{}", line) {
+            warn!("cant write output {}", err);
+        }
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::new(io::sink())
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_link_record_json() {
+        let record = LinkRecord {
+            event: "plan",
+            upper: Path::new("/home/me/.zshrc"),
+            target: Path::new("/home/me/.dots/zsh_all/.zshrc"),
+            package: "zsh_all",
+            action: "make",
+            result: "planned",
+            error: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            concat!(
+                r#"{"event":"plan","upper":"/home/me/.zshrc","#,
+                r#""target":"/home/me/.dots/zsh_all/.zshrc","#,
+                r#""package":"zsh_all","action":"make","result":"planned"}"#
+            )
+        );
+
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+
+        // a line per record in the writer that was given
+        let out_file = tempdir().unwrap().into_path().join("out.json");
+        let output = Output::new(File::create(&out_file).unwrap());
+        output.record(&record);
+        output.record(&record);
+
+        let written = fs::read_to_string(&out_file).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        assert!(written.starts_with(r#"{"event":"plan""#), "{}", written);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// a file at the top of a package holding where the package is linked to
+pub const ROOT_FILE: &str = ".manage_root";
+
+// dirs at the top of a package named @NAME are linked in to the NAME root
+// instead of the package root
+pub const ROOT_NAMES: &[(&str, &str, &str)] = &[
+    ("config", "XDG_CONFIG_HOME", ".config"),
+    ("data", "XDG_DATA_HOME", ".local/share"),
+    ("cache", "XDG_CACHE_HOME", ".cache"),
+    ("state", "XDG_STATE_HOME", ".local/state"),
+    ("bin", "XDG_BIN_HOME", ".local/bin"),
+];
+
+// the name of a @NAME dir
+pub fn root_name(name: &OsStr) -> Option<&str> {
+    name.to_str()?.strip_prefix('@')
+}
+
+// the top of a package holds the ROOT_FILE and @NAME dirs, they are never
+// linked them selves
+pub fn is_package_meta(target_path: &Path, under_dir: &Path) -> bool {
+    let at_top = target_path
+        .parent()
+        .and_then(Path::parent)
+        .map(|parent| parent == under_dir)
+        .unwrap_or(false);
+
+    match target_path.file_name() {
+        Some(name) if at_top => name == ROOT_FILE || root_name(name).is_some(),
+        _ => false,
+    }
+}
+
+// the xdg dirs from the env falling back to their place in upper_dir
+pub fn xdg_roots(upper_dir: &Path) -> BTreeMap<String, PathBuf> {
+    ROOT_NAMES
+        .iter()
+        .map(|(name, env_var, default)| {
+            let root = match env::var(env_var) {
+                Ok(var) if Path::new(&var).is_absolute() => PathBuf::from(var),
+                _ => upper_dir.join(default),
+            };
+
+            (name.to_string(), root)
+        })
+        .collect()
+}
+
+// where each package and each @NAME dir is linked to
+#[derive(Default, Debug)]
+pub struct DestRoots {
+    upper_dir: PathBuf,
+    // package name to its destination from the config
+    packages: BTreeMap<String, String>,
+    named: BTreeMap<String, PathBuf>,
+}
+
+impl DestRoots {
+    pub fn new(
+        upper_dir: &Path,
+        packages: &BTreeMap<String, String>,
+        named: BTreeMap<String, PathBuf>,
+    ) -> Self {
+        DestRoots {
+            upper_dir: upper_dir.to_owned(),
+            packages: packages.clone(),
+            named,
+        }
+    }
+
+    // @config/nvim starts from a named root, ~/ and relative paths start
+    // from upper_dir
+    pub fn expand(&self, dest: &str) -> Result<PathBuf, Box<dyn Error>> {
+        if let Some(named) = dest.strip_prefix('@') {
+            let (name, rest) = match named.find('/') {
+                Some(index) => (&named[..index], &named[index + 1..]),
+                None => (named, ""),
+            };
+
+            let root = self
+                .named
+                .get(name)
+                .ok_or_else(|| format!("unknown root @{}", name))?;
+
+            return Ok(root.join(rest));
+        }
+
+        Ok(self.upper_dir.join(dest.trim_start_matches("~/")))
+    }
+
+    // the config first then the packages ROOT_FILE then upper_dir
+    pub fn package_root(
+        &self,
+        package_dir: &Path,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let package = package_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        if let Some(dest) = self.packages.get(&package) {
+            return self.expand(dest);
+        }
+
+        let root_file = package_dir.join(ROOT_FILE);
+
+        if root_file.is_file() {
+            let dest = fs::read_to_string(&root_file)
+                .map_err(|err| format!("cant read {:?} {}", root_file, err))?;
+
+            return self.expand(dest.trim());
+        }
+
+        Ok(self.upper_dir.clone())
+    }
+
+    // every dir of the package to walk and the root it is linked in to, the
+    // package its self first then each @NAME dir
+    pub fn package_dirs(
+        &self,
+        package_dir: &Path,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn Error>> {
+        let mut to_ret: Vec<(PathBuf, PathBuf)> =
+            vec![(self.package_root(package_dir)?, package_dir.to_owned())];
+
+        for entry in fs::read_dir(package_dir)? {
+            let entry = entry?;
+
+            let name = match root_name(&entry.file_name()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            if !entry.path().is_dir() {
+                return Err(Box::from(format!(
+                    "@{} needs to be a dir in {:?}",
+                    name, package_dir
+                )));
+            }
+
+            let root = self.named.get(&name).ok_or_else(|| {
+                format!("unknown root @{} in {:?}", name, package_dir)
+            })?;
+
+            to_ret.push((root.clone(), entry.path()));
+        }
+
+        Ok(to_ret)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_package_dirs() {
+        let temp_path = tempdir().unwrap().into_path();
+        let under_dir = temp_path.join(".under");
+        let nvim_dir = under_dir.join("nvim_all");
+        let fonts_dir = under_dir.join("fonts_all");
+
+        fs::create_dir_all(nvim_dir.join("@config/nvim")).unwrap();
+        fs::create_dir_all(nvim_dir.join("@data")).unwrap();
+        fs::create_dir_all(&fonts_dir).unwrap();
+        fs::write(fonts_dir.join(ROOT_FILE), "@data/fonts\n").unwrap();
+
+        let mut packages = BTreeMap::new();
+        packages.insert("nvim_all".to_string(), "/etc/nvim".to_string());
+
+        let config_root = temp_path.join(".config");
+        let data_root = temp_path.join(".local/share");
+
+        let mut named = BTreeMap::new();
+        named.insert("config".to_string(), config_root.clone());
+        named.insert("data".to_string(), data_root.clone());
+
+        let roots = DestRoots::new(&temp_path, &packages, named);
+
+        let mut nvim_dirs = roots.package_dirs(&nvim_dir).unwrap();
+        nvim_dirs[1..].sort();
+
+        assert_eq!(
+            nvim_dirs,
+            vec![
+                (PathBuf::from("/etc/nvim"), nvim_dir.clone()),
+                (config_root, nvim_dir.join("@config")),
+                (data_root.clone(), nvim_dir.join("@data")),
+            ]
+        );
+
+        assert_eq!(
+            roots.package_root(&fonts_dir).unwrap(),
+            data_root.join("fonts")
+        );
+        assert_eq!(roots.expand("~/bin").unwrap(), temp_path.join("bin"));
+        assert!(roots.expand("@nowhere/x").is_err());
+
+        assert!(is_package_meta(&nvim_dir.join("@config"), &under_dir));
+        assert!(is_package_meta(&fonts_dir.join(ROOT_FILE), &under_dir));
+        assert!(!is_package_meta(
+            &nvim_dir.join("@config/@types"),
+            &under_dir
+        ));
+    }
+}
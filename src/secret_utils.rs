@@ -0,0 +1,298 @@
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use log::warn;
+use tempfile::Builder;
+
+// files ending in .enc are decrypted in to upper_dir instead of linked
+pub const SECRET_EXT: &str = "enc";
+
+// only the owner can read a decrypted secret or the key
+pub const SECRET_MODE: u32 = 0o600;
+
+// the start of every encrypted file so a plain file is never taken for one
+const MAGIC: &[u8] = b"manage-enc-1\n";
+const NONCE_LEN: usize = 12;
+
+pub fn is_secret(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new(SECRET_EXT)) && path.is_file()
+}
+
+// $XDG_DATA_HOME/manage/secret.key falling back to ~/.local/share, the
+// config its self is the file $XDG_CONFIG_HOME/manage
+pub fn default_key_file() -> Result<PathBuf, String> {
+    if let Ok(var) = env::var("XDG_DATA_HOME") {
+        if !var.is_empty() {
+            return Ok(Path::new(&var).join("manage/secret.key"));
+        }
+    }
+
+    match env::var("HOME") {
+        Ok(home) => Ok(Path::new(&home).join(".local/share/manage/secret.key")),
+        Err(err) => Err(format!("no XDG_DATA_HOME or HOME {}", err)),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+// write a file only the owner can read, it is never readable by others even
+// for a moment
+fn write_private(path: &Path, content: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(SECRET_MODE)
+        .open(path)
+        .map_err(|err| format!("cant write {:?} {}", path, err))?;
+
+    // an old file keeps its mode so set it again
+    file.set_permissions(fs::Permissions::from_mode(SECRET_MODE))?;
+    file.write_all(content)?;
+
+    Ok(())
+}
+
+// a chacha20poly1305 key kept as hex in a file out side under_dir
+pub struct SecretKey {
+    key: Key,
+}
+
+impl SecretKey {
+    pub fn load(key_file: &Path) -> Result<Self, Box<dyn Error>> {
+        let meta = fs::metadata(key_file).map_err(|err| {
+            format!("cant read secret key {:?} {}", key_file, err)
+        })?;
+
+        if meta.permissions().mode() & 0o077 != 0 {
+            return Err(Box::from(format!(
+                "secret key {:?} can be read by others, chmod 600 it",
+                key_file
+            )));
+        }
+
+        let hex = fs::read_to_string(key_file)?;
+
+        match from_hex(hex.trim()) {
+            Some(bytes) if bytes.len() == 32 => Ok(SecretKey {
+                key: *Key::from_slice(&bytes),
+            }),
+            _ => Err(Box::from(format!("bad secret key {:?}", key_file))),
+        }
+    }
+
+    // make a new key the first time something is encrypted
+    pub fn load_or_make(key_file: &Path) -> Result<Self, Box<dyn Error>> {
+        if key_file.exists() {
+            return SecretKey::load(key_file);
+        }
+
+        if let Some(parent) = key_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        write_private(key_file, format!("{}\n", to_hex(&key)).as_bytes())?;
+
+        warn!("made new secret key {:?}, keep a copy of it", key_file);
+
+        Ok(SecretKey { key })
+    }
+
+    pub fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let cipher = ChaCha20Poly1305::new(&self.key);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let sealed = cipher
+            .encrypt(&nonce, plain)
+            .map_err(|_| "cant encrypt secret")?;
+
+        let mut to_ret: Vec<u8> = MAGIC.to_vec();
+        to_ret.extend_from_slice(&nonce);
+        to_ret.extend_from_slice(&sealed);
+
+        Ok(to_ret)
+    }
+
+    pub fn decrypt(&self, secret: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let body = secret
+            .strip_prefix(MAGIC)
+            .filter(|body| body.len() > NONCE_LEN)
+            .ok_or("not a manage secret")?;
+
+        let (nonce, sealed) = body.split_at(NONCE_LEN);
+
+        ChaCha20Poly1305::new(&self.key)
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| Box::from("cant decrypt, wrong key or changed file"))
+    }
+}
+
+pub fn decrypt_file(
+    secret_file: &Path,
+    key_file: &Path,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let secret = fs::read(secret_file)
+        .map_err(|err| format!("cant read {:?} {}", secret_file, err))?;
+
+    SecretKey::load(key_file)?
+        .decrypt(&secret)
+        .map_err(|err| Box::from(format!("{:?}: {}", secret_file, err)))
+}
+
+// write a decrypted secret where only the owner can read it
+pub fn write_secret(
+    upper_file: &Path,
+    plain: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    write_private(upper_file, plain)
+}
+
+fn check_secret_name(secret_file: &Path) -> Result<(), Box<dyn Error>> {
+    if secret_file.extension() == Some(OsStr::new(SECRET_EXT)) {
+        Ok(())
+    } else {
+        Err(Box::from(format!(
+            "{:?} needs to end in .{}",
+            secret_file, SECRET_EXT
+        )))
+    }
+}
+
+// encrypt a file from out side the repo in to the repo
+pub fn encrypt_file(
+    plain_file: &Path,
+    secret_file: &Path,
+    key_file: &Path,
+) -> Result<(), Box<dyn Error>> {
+    check_secret_name(secret_file)?;
+
+    let plain = fs::read(plain_file)
+        .map_err(|err| format!("cant read {:?} {}", plain_file, err))?;
+
+    let secret = SecretKey::load_or_make(key_file)?.encrypt(&plain)?;
+
+    fs::write(secret_file, secret)
+        .map_err(|err| format!("cant write {:?} {}", secret_file, err))?;
+
+    Ok(())
+}
+
+// decrypt in to a private temp dir, open $EDITOR on it then encrypt it back
+// when it changed, a missing secret starts empty
+pub fn edit_secret(
+    secret_file: &Path,
+    key_file: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    check_secret_name(secret_file)?;
+
+    let key = SecretKey::load_or_make(key_file)?;
+
+    let plain = if secret_file.exists() {
+        key.decrypt(&fs::read(secret_file)?)?
+    } else {
+        vec![]
+    };
+
+    // the dir is only readable by us and is removed when dropped
+    let temp_dir = Builder::new().prefix("manage-secret").tempdir()?;
+    let name = secret_file.file_stem().ok_or("cant get secret name")?;
+    let temp_file = temp_dir.path().join(name);
+
+    write_private(&temp_file, &plain)?;
+
+    let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+
+    // through sh so EDITOR can hold args
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&temp_file)
+        .status()
+        .map_err(|err| format!("cant run {} {}", editor, err))?;
+
+    if !status.success() {
+        return Err(Box::from(format!("{} failed with {}", editor, status)));
+    }
+
+    let edited = fs::read(&temp_file)?;
+
+    if edited == plain {
+        return Ok(false);
+    }
+
+    fs::write(secret_file, key.encrypt(&edited)?)
+        .map_err(|err| format!("cant write {:?} {}", secret_file, err))?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let temp_path = tempdir().unwrap().into_path();
+        let key_file = temp_path.join("keys/secret.key");
+        let plain_file = temp_path.join("netrc");
+        let secret_file = temp_path.join("netrc.enc");
+
+        fs::write(&plain_file, "machine example.com password hunter2\n")
+            .unwrap();
+
+        if let Err(err) = encrypt_file(&plain_file, &secret_file, &key_file) {
+            panic!("cant encrypt {}", err);
+        }
+
+        let key_mode = fs::metadata(&key_file).unwrap().permissions().mode();
+        assert_eq!(key_mode & 0o777, SECRET_MODE);
+
+        let secret = fs::read(&secret_file).unwrap();
+        assert!(secret.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&secret).contains("hunter2"));
+
+        match decrypt_file(&secret_file, &key_file) {
+            Ok(plain) => assert_eq!(plain, fs::read(&plain_file).unwrap()),
+            Err(err) => panic!("cant decrypt {}", err),
+        }
+
+        // any change to the file is caught
+        let mut changed = secret.clone();
+        let last = changed.len() - 1;
+        changed[last] ^= 1;
+        fs::write(&secret_file, changed).unwrap();
+
+        assert!(decrypt_file(&secret_file, &key_file).is_err());
+
+        assert!(
+            encrypt_file(&plain_file, &temp_path.join("netrc"), &key_file)
+                .is_err(),
+            "secret without .enc"
+        );
+    }
+}
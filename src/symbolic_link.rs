@@ -1,31 +1,326 @@
 use std::env::set_current_dir;
 use std::error::Error;
+use std::ffi::OsStr;
 use std::fmt;
 use std::fs;
 use std::os::unix;
-use std::path::PathBuf;
-
-use crate::{my_utils::vec_to_string, Action, MainApp};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::{debug, error, info, trace};
+use serde::Deserialize;
+
+use crate::{
+    backup_utils::{backup_path, move_path, restore_backups},
+    copy_utils::{file_checksum, is_deployed, CopyRule},
+    error::Error as ManageError,
+    git_utils::head_commit_id,
+    hook_utils::{run_hook, run_hooks, HookKind, HOOKS_DIR},
+    ignore_utils::{IgnoreRules, IGNORE_FILE},
+    link_manifest::{Manifest, ManifestEntry},
+    link_status::print_status,
+    link_transaction::{rollback_error, Transaction},
+    my_utils::{relative_path, vec_to_string},
+    output_utils::{LinkRecord, OutputFormat},
+    root_utils::is_package_meta,
+    secret_utils::{decrypt_file, is_secret, write_secret, SECRET_EXT},
+    template_utils::{
+        is_template, line_diff, read_rendered, render_template, rendered_name,
+        TEMPLATE_EXT,
+    },
+    Action, MainApp,
+};
 
 type WorkResult = Result<bool, Box<dyn Error>>;
 
-#[derive(Debug)]
+// what to do when a real file is where a link should go
+#[derive(Deserialize, Default, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Abort,
+    Skip,
+    Backup,
+    Overwrite,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "abort" => Ok(ConflictPolicy::Abort),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "backup" => Ok(ConflictPolicy::Backup),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            _ => Err(format!("unknown conflict policy {}", policy)),
+        }
+    }
+}
+
+// how a file in a target dir gets in to upper_dir
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LinkKind {
+    Link,
+    // rendered in to a real file
+    Template,
+    // decrypted in to a real file only the owner can read
+    Secret,
+    // copied in to a real file
+    Copy,
+    // a dir link of another package made in to a real dir of links, the
+    // target_file is that packages dir
+    Unfold,
+    // skipped by an ignore pattern, only walked for status
+    Ignored,
+}
+
+// how the path in a new link is written
+#[derive(Deserialize, Default, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    #[default]
+    Absolute,
+    // from the links dir so links survive home moving
+    Relative,
+}
+
+#[derive(Clone, Debug)]
 pub struct SymLink {
     pub upper_file: PathBuf,
     pub target_file: PathBuf,
     pub exists: bool,
+    pub conflict: bool,
+    pub kind: LinkKind,
+    // the checksum of a copy when it was deployed
+    pub deployed: Option<String>,
 }
 
 impl SymLink {
-    pub fn new(up: &PathBuf, lo: &PathBuf, exists: bool) -> Self {
+    pub fn new(up: &Path, lo: &Path, exists: bool) -> Self {
         SymLink {
             upper_file: up.to_owned(),
             target_file: lo.to_owned(),
             exists,
+            conflict: false,
+            kind: LinkKind::Link,
+            deployed: None,
+        }
+    }
+
+    pub fn new_copy(up: &Path, lo: &Path, exists: bool) -> Self {
+        SymLink {
+            kind: LinkKind::Copy,
+            ..SymLink::new(up, lo, exists)
+        }
+    }
+
+    // a template or secret written in to a real file
+    pub fn new_rendered(
+        up: &Path,
+        lo: &Path,
+        exists: bool,
+        kind: LinkKind,
+    ) -> Self {
+        SymLink {
+            kind,
+            ..SymLink::new(up, lo, exists)
+        }
+    }
+
+    pub fn new_unfold(up: &Path, lo: &Path) -> Self {
+        SymLink {
+            kind: LinkKind::Unfold,
+            ..SymLink::new(up, lo, true)
+        }
+    }
+
+    // a link that has a real file in its place
+    pub fn new_conflict(up: &Path, lo: &Path) -> Self {
+        SymLink {
+            conflict: true,
+            ..SymLink::new(up, lo, false)
+        }
+    }
+
+    // get the real file out of the way by the conflict policy
+    fn clear_conflict(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        if !self.conflict {
+            return Ok(false);
+        }
+
+        match main_app.on_conflict {
+            ConflictPolicy::Backup => {
+                let backup_file = backup_path(
+                    &main_app.upper_dir,
+                    &main_app.backup_dir,
+                    &self.upper_file,
+                )?;
+
+                trans.moved(&self.upper_file, &backup_file, true);
+            }
+            ConflictPolicy::Overwrite => {
+                // keep the file next to the link until the target dir is
+                // done so it can be put back
+                let stash_file = self.hidden_sibling("manage_old")?;
+
+                move_path(&self.upper_file, &stash_file)?;
+
+                trans.moved(&self.upper_file, &stash_file, false);
+            }
+            _ => {
+                return Err(Box::from(ManageError::Conflict {
+                    path: self.upper_file.clone(),
+                    target: self.target_file.clone(),
+                }))
+            }
+        }
+
+        Ok(true)
+    }
+
+    // .NAME.suffix next to the upper_file
+    fn hidden_sibling(&self, suffix: &str) -> Result<PathBuf, Box<dyn Error>> {
+        hidden_sibling(&self.upper_file, suffix)
+    }
+
+    // the copy was edited since it was deployed
+    fn drifted(&self) -> Result<bool, Box<dyn Error>> {
+        match &self.deployed {
+            Some(sum) => Ok(file_checksum(&self.upper_file)? != *sum),
+            None => Ok(false),
+        }
+    }
+
+    fn drift_error(&self) -> Box<dyn Error> {
+        Box::from(ManageError::NotOwned {
+            path: self.upper_file.clone(),
+            message: format!(
+                "local edits in {:?}, use --write-back to keep them",
+                self.upper_file
+            ),
+        })
+    }
+
+    // put the edited copy in to under_dir
+    fn write_back(
+        &self,
+        trans: &mut Transaction,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.target_file.exists() {
+            let stash_file = hidden_sibling(&self.target_file, "manage_old")?;
+            move_path(&self.target_file, &stash_file)?;
+            trans.moved(&self.target_file, &stash_file, false);
+        }
+
+        fs::copy(&self.upper_file, &self.target_file)
+            .map_err(ManageError::io_at("cant write back", &self.upper_file))?;
+
+        Ok(())
+    }
+
+    // copy the target over the upper_file, local edits are written back to
+    // under_dir first when asked or stop the copy
+    fn write_copy(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        if self.exists && self.drifted()? {
+            if !main_app.write_back {
+                return Err(self.drift_error());
+            }
+
+            self.write_back(trans)?;
+            return Ok(true);
+        }
+
+        if self.exists && is_deployed(&self.upper_file, &self.target_file) {
+            return Ok(false);
+        }
+
+        match self.upper_file.symlink_metadata() {
+            // a link from before the file was copied
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let link_dest = self.upper_file.read_link().map_err(
+                    ManageError::io_at("cant read", &self.upper_file),
+                )?;
+                fs::remove_file(&self.upper_file).map_err(
+                    ManageError::io_at("cant remove link", &self.upper_file),
+                )?;
+                trans.removed(&self.upper_file, &link_dest);
+            }
+            Ok(_) => {
+                let stash_file = self.hidden_sibling("manage_old")?;
+                move_path(&self.upper_file, &stash_file)?;
+                trans.moved(&self.upper_file, &stash_file, false);
+            }
+            Err(_) => make_parent_dirs(&self.upper_file, trans)?,
+        }
+
+        fs::copy(&self.target_file, &self.upper_file)
+            .map_err(ManageError::io_at("cant copy", &self.upper_file))?;
+        trans.made(&self.upper_file);
+
+        Ok(true)
+    }
+
+    fn delete_copy(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        if !self.exists {
+            return Ok(false);
+        }
+
+        if self.drifted()? {
+            if !main_app.write_back {
+                return Err(self.drift_error());
+            }
+
+            self.write_back(trans)?;
+        } else if self.deployed.is_none()
+            && !is_deployed(&self.upper_file, &self.target_file)
+        {
+            return Err(Box::from(ManageError::NotOwned {
+                path: self.upper_file.clone(),
+                message: format!(
+                    "file was not copied by manage {:?}",
+                    self.upper_file
+                ),
+            }));
+        }
+
+        let stash_file = self.hidden_sibling("manage_old")?;
+        move_path(&self.upper_file, &stash_file)?;
+        trans.moved(&self.upper_file, &stash_file, false);
+
+        Ok(true)
+    }
+
+    // remove the upper_file what ever kind it is
+    fn delete(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        match self.kind {
+            LinkKind::Link => self.delete_symlink(trans),
+            LinkKind::Template | LinkKind::Secret => {
+                self.delete_rendered(main_app, trans)
+            }
+            LinkKind::Copy => self.delete_copy(main_app, trans),
+            // the other package still wants its links
+            LinkKind::Unfold | LinkKind::Ignored => Ok(false),
         }
     }
 
-    fn delete_symlink(&self) -> WorkResult {
+    fn delete_symlink(&self, trans: &mut Transaction) -> WorkResult {
         if !self.exists {
             return Ok(false);
         }
@@ -34,193 +329,1740 @@ impl SymLink {
             return Err(Box::from("upper_dir is pointing to target_dir"));
         }
 
-        match fs::remove_file(&self.upper_file) {
-            Err(err) => Err(Box::from(format!("cant delete symlink {}", err))),
-            Ok(_) => Ok(true),
-        }
+        let doing = "cant delete symlink";
+
+        let link_dest = self
+            .upper_file
+            .read_link()
+            .map_err(ManageError::io_at(doing, &self.upper_file))?;
+
+        fs::remove_file(&self.upper_file)
+            .map_err(ManageError::io_at(doing, &self.upper_file))?;
+        trans.removed(&self.upper_file, &link_dest);
+
+        Ok(true)
+    }
+
+    // what the link should hold for the link style
+    fn link_dest(&self, style: &LinkStyle) -> PathBuf {
+        let parent = match (style, self.upper_file.parent()) {
+            (LinkStyle::Relative, Some(parent)) => parent,
+            _ => return self.target_file.clone(),
+        };
+
+        let real_parent =
+            parent.canonicalize().unwrap_or_else(|_| parent.to_owned());
+
+        relative_path(&real_parent, &self.target_file)
     }
 
-    fn make_symlink(&self) -> WorkResult {
+    fn make_symlink(
+        &self,
+        style: &LinkStyle,
+        trans: &mut Transaction,
+    ) -> WorkResult {
         if self.exists {
             return Ok(false);
         }
 
-        if let Err(err) = unix::fs::symlink(&self.target_file, &self.upper_file)
-        {
-            Err(Box::from(format!("cant make symlink {}", err)))
+        // packages that dont fold get real dirs
+        make_parent_dirs(&self.upper_file, trans)?;
+
+        unix::fs::symlink(self.link_dest(style), &self.upper_file).map_err(
+            ManageError::io_at("cant make symlink", &self.upper_file),
+        )?;
+        trans.made(&self.upper_file);
+
+        Ok(true)
+    }
+
+    // swap the dir link for a real dir with a link to each thing in the dir
+    fn unfold(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        if !link_points_to(&self.upper_file, &self.target_file) {
+            return Ok(false);
+        }
+
+        let unfold_err = || ManageError::io_at("cant unfold", &self.upper_file);
+
+        let link_dest = self.upper_file.read_link().map_err(unfold_err())?;
+
+        fs::remove_file(&self.upper_file).map_err(unfold_err())?;
+        trans.removed(&self.upper_file, &link_dest);
+
+        fs::create_dir(&self.upper_file).map_err(unfold_err())?;
+        trans.made_dir(&self.upper_file);
+
+        let read_err = || ManageError::io_at("cant read", &self.target_file);
+
+        for entry in fs::read_dir(&self.target_file).map_err(read_err())? {
+            let target_path = entry.map_err(read_err())?.path();
+
+            if main_app.ignore.is_ignored(&target_path) {
+                continue;
+            }
+
+            let name = target_path.file_name().ok_or("cant get file name")?;
+
+            SymLink::new(&self.upper_file.join(name), &target_path, false)
+                .make_symlink(&main_app.link_style, trans)?;
+        }
+
+        Ok(true)
+    }
+
+    // the upper_file holds what the template renders to or the secret
+    // decrypts to
+    pub fn is_rendered(
+        &self,
+        main_app: &MainApp,
+    ) -> Result<bool, Box<dyn Error>> {
+        let rendered = self.rendered(main_app)?;
+
+        Ok(read_written(&self.upper_file).as_ref() == Some(&rendered))
+    }
+
+    // what a template renders to or a secret decrypts to
+    fn rendered(&self, main_app: &MainApp) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self.kind {
+            LinkKind::Secret => {
+                decrypt_file(&self.target_file, &main_app.secret_key_file)
+            }
+            _ => {
+                Ok(render_template(&self.target_file, &main_app.template_vars)?
+                    .into_bytes())
+            }
+        }
+    }
+
+    // write the template or secret over the upper_file when it changed
+    fn write_rendered(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        let rendered = self.rendered(main_app)?;
+
+        if self.exists {
+            if read_written(&self.upper_file).as_ref() == Some(&rendered) {
+                return Ok(false);
+            }
+
+            let stash_file = self.hidden_sibling("manage_old")?;
+            move_path(&self.upper_file, &stash_file)?;
+            trans.moved(&self.upper_file, &stash_file, false);
+        }
+
+        if self.kind == LinkKind::Secret {
+            write_secret(&self.upper_file, &rendered)?;
         } else {
-            Ok(true)
+            fs::write(&self.upper_file, rendered)
+                .map_err(ManageError::io_at("cant render", &self.upper_file))?;
+        }
+
+        if !self.exists {
+            trans.made(&self.upper_file);
+        }
+
+        // keep scripts executable, secrets stay owner only
+        if self.kind != LinkKind::Secret {
+            let permissions = fs::metadata(&self.target_file)
+                .map_err(ManageError::io_at("cant read", &self.target_file))?
+                .permissions();
+
+            fs::set_permissions(&self.upper_file, permissions).map_err(
+                ManageError::io_at("cant set permissions", &self.upper_file),
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    // only remove a rendered file that has not been changed by hand, the
+    // checksum from the manifest is used when there is one so a file from a
+    // package that is gone does not need rendering
+    fn delete_rendered(
+        &self,
+        main_app: &MainApp,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        if !self.exists {
+            return Ok(false);
+        }
+
+        let changed = match self.deployed {
+            Some(_) => self.drifted()?,
+            None => !self.is_rendered(main_app)?,
+        };
+
+        if changed {
+            return Err(Box::from(ManageError::NotOwned {
+                path: self.upper_file.clone(),
+                message: format!(
+                    "{} file was changed {:?}",
+                    rendered_verb(self.kind).1,
+                    self.upper_file
+                ),
+            }));
+        }
+
+        let stash_file = self.hidden_sibling("manage_old")?;
+        move_path(&self.upper_file, &stash_file)?;
+        trans.moved(&self.upper_file, &stash_file, false);
+
+        Ok(true)
+    }
+
+    // make the new link next to the old one then rename it over the top so
+    // the upper_file is never missing
+    fn remake_symlink(
+        &self,
+        style: &LinkStyle,
+        trans: &mut Transaction,
+    ) -> WorkResult {
+        if !self.exists {
+            return self.make_symlink(style, trans);
+        }
+
+        let old_dest = self.upper_file.read_link().map_err(
+            ManageError::io_at("cant read old symlink", &self.upper_file),
+        )?;
+
+        let tmp_file = self.hidden_sibling("manage_tmp")?;
+
+        if tmp_file.symlink_metadata().is_ok() {
+            fs::remove_file(&tmp_file).map_err(ManageError::io_at(
+                "cant clear old tmp link",
+                &tmp_file,
+            ))?;
+        }
+
+        unix::fs::symlink(self.link_dest(style), &tmp_file)
+            .map_err(ManageError::io_at("cant make tmp symlink", &tmp_file))?;
+
+        if let Err(err) = fs::rename(&tmp_file, &self.upper_file) {
+            let _ = fs::remove_file(&tmp_file);
+            return Err(Box::from(ManageError::io_at(
+                "cant replace symlink",
+                &self.upper_file,
+            )(err)));
         }
+
+        trans.replaced(&self.upper_file, &old_dest);
+        Ok(true)
     }
 }
 
 impl fmt::Display for SymLink {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let target = self.target_file.to_str().expect("cant display target");;
-        let upper = self.upper_file.to_str().expect("cant display upper");
-        write!(f, "{} -> {}", upper, target)
+        write!(
+            f,
+            "{} -> {}",
+            self.upper_file.display(),
+            self.target_file.display()
+        )
+    }
+}
+
+// the file as it is in upper_dir if it is a real file
+fn read_written(upper_file: &Path) -> Option<Vec<u8>> {
+    if upper_file.symlink_metadata().ok()?.file_type().is_file() {
+        fs::read(upper_file).ok()
+    } else {
+        None
+    }
+}
+
+// how to say a template or secret is written
+fn rendered_verb(kind: LinkKind) -> (&'static str, &'static str) {
+    match kind {
+        LinkKind::Secret => ("decrypt", "decrypted"),
+        _ => ("render", "rendered"),
+    }
+}
+
+fn dry_rendered(sym: &SymLink, main_app: &MainApp) -> WorkResult {
+    let (verb, done) = rendered_verb(sym.kind);
+
+    let work = match main_app.action {
+        Action::Make | Action::Remake => {
+            let rendered = sym.rendered(main_app)?;
+
+            let current = if sym.exists {
+                read_written(&sym.upper_file).unwrap_or_default()
+            } else {
+                vec![]
+            };
+
+            if sym.exists && current == rendered {
+                debug!("already {} {}", done, sym);
+                false
+            } else {
+                if sym.conflict {
+                    info!(
+                        "would {:?} then {} {}",
+                        main_app.on_conflict, verb, sym
+                    );
+                } else {
+                    info!("would {} {}", verb, sym);
+                }
+
+                // never print what is in a secret
+                if sym.kind == LinkKind::Template {
+                    info!(
+                        "{}",
+                        line_diff(
+                            &String::from_utf8_lossy(&current),
+                            &String::from_utf8_lossy(&rendered)
+                        )
+                    );
+                }
+                true
+            }
+        }
+        Action::Delete => {
+            if sym.exists {
+                info!("would delete {} {}", done, sym);
+                true
+            } else {
+                debug!("already missing {}", sym);
+                false
+            }
+        }
+        _ => return Err(Box::from("bad action")),
+    };
+
+    Ok(work)
+}
+
+fn dry_copy(sym: &SymLink, main_app: &MainApp) -> WorkResult {
+    if sym.exists && sym.drifted()? {
+        if !main_app.write_back {
+            return Err(sym.drift_error());
+        }
+
+        info!("would write back local edits {}", sym);
+
+        if main_app.action != Action::Delete {
+            return Ok(true);
+        }
     }
+
+    let work = match main_app.action {
+        Action::Make | Action::Remake => {
+            if sym.exists && is_deployed(&sym.upper_file, &sym.target_file) {
+                debug!("already copied {}", sym);
+                false
+            } else if sym.conflict {
+                info!("would {:?} then copy {}", main_app.on_conflict, sym);
+                true
+            } else {
+                info!("would copy {}", sym);
+
+                let current = read_rendered(&sym.upper_file);
+                let new = fs::read_to_string(&sym.target_file).ok();
+                if let (Some(current), Some(new)) = (current, new) {
+                    info!("{}", line_diff(&current, &new));
+                }
+
+                true
+            }
+        }
+        Action::Delete => {
+            if sym.exists {
+                info!("would delete copy {}", sym);
+                true
+            } else {
+                debug!("already missing {}", sym);
+                false
+            }
+        }
+        _ => return Err(Box::from("bad action")),
+    };
+
+    Ok(work)
 }
 
 fn dry_runner(sym: &SymLink, main_app: &MainApp) -> WorkResult {
+    match sym.kind {
+        LinkKind::Template | LinkKind::Secret => {
+            return dry_rendered(sym, main_app)
+        }
+        LinkKind::Copy => return dry_copy(sym, main_app),
+        LinkKind::Unfold if main_app.action == Action::Delete => {
+            return Ok(false)
+        }
+        LinkKind::Ignored => return Ok(false),
+        LinkKind::Unfold => {
+            info!("would unfold {}", sym);
+            return Ok(true);
+        }
+        LinkKind::Link => {}
+    }
+
     let work = match main_app.action {
         Action::Remake => {
-            println!("would remake {}", sym);
+            info!("would remake {}", sym);
             true
         }
         Action::Make => {
             if sym.exists {
-                println!("already made {}", sym);
+                debug!("already made {}", sym);
                 false
+            } else if sym.conflict {
+                info!("would {:?} then make {}", main_app.on_conflict, sym);
+                true
             } else {
-                println!("would make {}", sym);
+                info!("would make {}", sym);
                 true
             }
         }
         Action::Delete => {
             if sym.exists {
-                println!("would delete {}", sym);
+                info!("would delete {}", sym);
                 true
             } else {
-                println!("already missing {}", sym);
+                debug!("already missing {}", sym);
                 false
             }
         }
+        _ => return Err(Box::from("bad action")),
     };
 
     Ok(work)
 }
 
-fn live_runner(sym: &SymLink, main_app: &MainApp) -> WorkResult {
+fn live_runner(
+    sym: &SymLink,
+    main_app: &MainApp,
+    trans: &mut Transaction,
+) -> WorkResult {
+    if main_app.action != Action::Delete {
+        sym.clear_conflict(main_app, trans)?;
+    }
+
     let work = match main_app.action {
-        Action::Make => sym.make_symlink()?,
-        Action::Delete => sym.delete_symlink()?,
+        Action::Delete => sym.delete(main_app, trans)?,
+        Action::Make | Action::Remake => match sym.kind {
+            LinkKind::Template | LinkKind::Secret => {
+                sym.write_rendered(main_app, trans)?
+            }
+            LinkKind::Copy => sym.write_copy(main_app, trans)?,
+            LinkKind::Unfold => sym.unfold(main_app, trans)?,
+            LinkKind::Ignored => false,
+            LinkKind::Link if main_app.action == Action::Remake => {
+                sym.remake_symlink(&main_app.link_style, trans)?
+            }
+            LinkKind::Link => sym.make_symlink(&main_app.link_style, trans)?,
+        },
         _ => return Err(Box::from("bad action")),
     };
 
     Ok(work)
 }
 
+// what running the link does, for the json output
+fn link_action(sym: &SymLink, main_app: &MainApp) -> String {
+    let verb = match (&main_app.action, sym.kind) {
+        (Action::Delete, LinkKind::Copy) => "delete copy",
+        (Action::Delete, _) => "delete",
+        (_, LinkKind::Template) => "render",
+        (_, LinkKind::Secret) => "decrypt",
+        (_, LinkKind::Copy) => "copy",
+        (_, LinkKind::Unfold) => "unfold",
+        (_, LinkKind::Ignored) => "ignore",
+        (Action::Remake, LinkKind::Link) => "remake",
+        (_, LinkKind::Link) => "make",
+    };
+
+    if sym.conflict && main_app.action != Action::Delete {
+        let policy = format!("{:?}", main_app.on_conflict).to_lowercase();
+        format!("{} then {}", policy, verb)
+    } else {
+        verb.to_string()
+    }
+}
+
+// a json line for the link once it was planned or run
+fn report_link(
+    sym: &SymLink,
+    main_app: &MainApp,
+    action: &str,
+    work: &WorkResult,
+) {
+    if main_app.format != OutputFormat::Json {
+        return;
+    }
+
+    let result = match work {
+        Ok(true) if main_app.dry_run => "planned",
+        Ok(true) => "changed",
+        Ok(false) => "unchanged",
+        Err(_) => "failed",
+    };
+
+    main_app.output.record(&LinkRecord {
+        event: if main_app.dry_run { "plan" } else { "done" },
+        upper: &sym.upper_file,
+        target: &sym.target_file,
+        package: &package_name(&sym.target_file, &main_app.under_dir),
+        action,
+        result,
+        error: work.as_ref().err().map(|err| err.to_string()),
+    });
+}
+
+// apply the vec as one transaction, if any link fails the ones before it
+// are undone
 pub fn run_sym_vec(to_sym_vec: &[SymLink], main_app: &MainApp) -> WorkResult {
     if to_sym_vec.is_empty() {
         return Ok(false);
     }
 
+    let mut trans = Transaction::default();
+
     let mut did_work = false;
     for sym in to_sym_vec {
         let work = if main_app.dry_run {
-            dry_runner(&sym, main_app)?
+            dry_runner(sym, main_app)
         } else {
-            live_runner(&sym, main_app)?
+            live_runner(sym, main_app, &mut trans)
         };
 
-        if work {
-            did_work = true;
+        report_link(sym, main_app, &link_action(sym, main_app), &work);
+
+        match work {
+            Ok(true) => did_work = true,
+            Ok(false) => {}
+            Err(err) => return Err(rollback_error(err, trans)),
         }
     }
 
+    trans.commit()?;
+
     let msg = if did_work {
         format!("{}", main_app.action)
     } else {
         "did noting".to_string()
     };
 
-    main_app.verbose_ouput("", Some(&vec_to_string(&msg, &to_sym_vec)));
+    trace!("{}", vec_to_string(&msg, to_sym_vec));
 
     Ok(did_work)
 }
 
 // return symlinks we own return err on symlinks we dont
 pub fn symlink_check(
-    real_maybe: &PathBuf,
-    maybe_path: &PathBuf,
-    target_path: &PathBuf,
-    upper_dir: &PathBuf,
+    real_maybe: &Path,
+    maybe_path: &Path,
+    target_path: &Path,
 ) -> Result<SymLink, Box<dyn Error>> {
-    // idk why but real_maybe was being made relative
-    // ../.under/thing/other
-    let real_maybe = upper_dir
-        .join(real_maybe)
+    // relative links like ../.under/thing/other start from the links dir,
+    // absolute ones resolve to themselves
+    let real_maybe = resolve_link(maybe_path, real_maybe);
+    let real_target = target_path
         .canonicalize()
-        .map_err(|err| format!("cant canonicalize real_maybe {}", err))?;
+        .unwrap_or_else(|_| target_path.to_owned());
 
-    if target_path.as_os_str() == real_maybe.as_os_str() {
-        Ok(SymLink::new(&maybe_path, &target_path, true))
+    if real_target == real_maybe {
+        Ok(SymLink::new(maybe_path, target_path, true))
     } else {
-        Err(Box::from(format!(
-            "link is not owned by us {:?}",
-            maybe_path
-        )))
+        Err(Box::from(ManageError::NotOwned {
+            path: maybe_path.to_owned(),
+            message: format!("link is not owned by us {:?}", maybe_path),
+        }))
     }
 }
 
-pub fn get_symlink_vec(
-    upper_dir: &PathBuf,
-    target_dir: &PathBuf,
-) -> Result<Vec<SymLink>, Box<dyn Error>> {
-    let read_target_dir =
-        fs::read_dir(target_dir).expect("cant read target_path");
+// get the full path a link points to, relative links start from the links
+// parent dir
+pub fn resolve_link(link_path: &Path, link_dest: &Path) -> PathBuf {
+    let full_dest = match link_path.parent() {
+        Some(parent) => parent.join(link_dest),
+        None => link_dest.to_path_buf(),
+    };
 
-    let mut to_ret: Vec<SymLink> = vec![];
+    full_dest.canonicalize().unwrap_or(full_dest)
+}
+
+// the link exists and resolves to target
+pub fn link_points_to(link: &Path, target: &Path) -> bool {
+    match link.read_link() {
+        Ok(link_dest) => {
+            let real_target =
+                target.canonicalize().unwrap_or_else(|_| target.to_owned());
+
+            resolve_link(link, &link_dest) == real_target
+        }
+        Err(_) => false,
+    }
+}
+
+// the package is the first dir of the target under under_dir
+fn package_name(target_file: &Path, under_dir: &Path) -> String {
+    target_file
+        .strip_prefix(under_dir)
+        .ok()
+        .and_then(|relative| relative.components().next())
+        .map(|first| first.as_os_str().to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// add every link in the vec that points where it should to the manifest,
+// copies and rendered files with the checksum of what was written
+fn record_links(
+    manifest: &mut Manifest,
+    syms: &[SymLink],
+    main_app: &MainApp,
+    commit: &str,
+) {
+    let under_dir = &main_app.under_dir;
+
+    for sym in syms {
+        if sym.kind == LinkKind::Unfold {
+            record_unfolded(manifest, sym, under_dir, commit);
+            continue;
+        }
+
+        let checksum = match sym.kind {
+            LinkKind::Copy
+                if is_deployed(&sym.upper_file, &sym.target_file) =>
+            {
+                file_checksum(&sym.upper_file).ok()
+            }
+            LinkKind::Template | LinkKind::Secret
+                if sym.is_rendered(main_app).unwrap_or(false) =>
+            {
+                file_checksum(&sym.upper_file).ok()
+            }
+            LinkKind::Link
+                if link_points_to(&sym.upper_file, &sym.target_file) =>
+            {
+                None
+            }
+            _ => continue,
+        };
+
+        manifest.record(ManifestEntry {
+            link: sym.upper_file.clone(),
+            target: sym.target_file.clone(),
+            package: package_name(&sym.target_file, under_dir),
+            commit: commit.to_string(),
+            checksum,
+        });
+    }
+}
+
+// the dir link is gone and each link in the new dir belongs to the package
+// that had the dir
+fn record_unfolded(
+    manifest: &mut Manifest,
+    sym: &SymLink,
+    under_dir: &Path,
+    commit: &str,
+) {
+    let is_real_dir = sym
+        .upper_file
+        .symlink_metadata()
+        .map(|meta| meta.is_dir())
+        .unwrap_or(false);
+
+    let read_dir = match fs::read_dir(&sym.target_file) {
+        Ok(read_dir) if is_real_dir => read_dir,
+        _ => return,
+    };
+
+    manifest.remove(&sym.upper_file);
+    manifest.record_unfolded(&sym.upper_file);
+
+    for entry in read_dir.flatten() {
+        let target = entry.path();
+        let link = sym.upper_file.join(entry.file_name());
+
+        if link_points_to(&link, &target) {
+            manifest.record(ManifestEntry {
+                link,
+                package: package_name(&target, under_dir),
+                target,
+                commit: commit.to_string(),
+                checksum: None,
+            });
+        }
+    }
+}
+
+// a link, copy or rendered file made from a manifest entry, rendered files
+// are told from copies by the extension of their target
+fn entry_symlink(entry: &ManifestEntry) -> SymLink {
+    let extension = entry.target.extension();
+
+    let kind = if entry.checksum.is_none() {
+        LinkKind::Link
+    } else if extension == Some(OsStr::new(TEMPLATE_EXT)) {
+        LinkKind::Template
+    } else if extension == Some(OsStr::new(SECRET_EXT)) {
+        LinkKind::Secret
+    } else {
+        LinkKind::Copy
+    };
+
+    SymLink {
+        kind,
+        deployed: entry.checksum.clone(),
+        ..SymLink::new(&entry.link, &entry.target, true)
+    }
+}
+
+// manifest entries of this under_dir for the selected packages and for
+// packages that are no longer in it, the manifest is shared by every repo
+fn selected_entries<'a>(
+    manifest: &'a Manifest,
+    main_app: &MainApp,
+) -> Vec<&'a ManifestEntry> {
+    let packages: Vec<String> = main_app
+        .target_dirs
+        .iter()
+        .map(|target_dir| package_name(target_dir, &main_app.under_dir))
+        .collect();
+
+    manifest
+        .links
+        .iter()
+        .filter(|entry| entry.target.starts_with(&main_app.under_dir))
+        .filter(|entry| {
+            packages.contains(&entry.package)
+                || !main_app.under_dir.join(&entry.package).is_dir()
+        })
+        .collect()
+}
+
+// a real file is where the link should go
+fn conflict_check(
+    maybe_path: &Path,
+    target_path: &Path,
+    on_conflict: &ConflictPolicy,
+) -> Result<Option<SymLink>, Box<dyn Error>> {
+    match on_conflict {
+        ConflictPolicy::Abort => Err(Box::from(ManageError::Conflict {
+            path: maybe_path.to_owned(),
+            target: target_path.to_owned(),
+        })),
+        ConflictPolicy::Skip => {
+            info!("skipping existing file {:?}", maybe_path);
+            Ok(None)
+        }
+        _ => Ok(Some(SymLink::new_conflict(maybe_path, target_path))),
+    }
+}
+
+// what walk_target_dir needs to know beyond the two dirs
+pub struct WalkOptions<'a> {
+    pub on_conflict: &'a ConflictPolicy,
+    // when remaking any link in to under_dir can be replaced
+    pub remake_under: Option<&'a Path>,
+    pub copy_rule: &'a CopyRule,
+    pub ignore: &'a IgnoreRules,
+    // to tell links of other packages from links we dont own
+    pub under_dir: &'a Path,
+    // dirs missing from upper_dir can be linked whole
+    pub folding: bool,
+    // status wants every path, ignored paths are kept and links we dont own
+    // come back as conflicts instead of stopping the walk
+    pub report: bool,
+}
+
+// make any missing dirs above path
+fn make_parent_dirs(
+    path: &Path,
+    trans: &mut Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let missing: Vec<&Path> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.symlink_metadata().is_err())
+        .collect();
+
+    for dir in missing.iter().rev() {
+        fs::create_dir(dir)
+            .map_err(ManageError::io_at("cant make dir", dir))?;
+        trans.made_dir(dir);
+    }
+
+    Ok(())
+}
+
+// .NAME.suffix next to path
+fn hidden_sibling(
+    path: &Path,
+    suffix: &str,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("cant get file name")?;
+
+    Ok(path.with_file_name(format!(".{}.{}", file_name, suffix)))
+}
+
+// copies are real files in upper_dir, a copied dir is walked in to even when
+// it is not in upper_dir yet
+fn copy_check(
+    maybe_path: &Path,
+    target_path: &Path,
+    options: &WalkOptions,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    let meta = maybe_path.symlink_metadata();
+
+    if target_path.is_dir() {
+        return match meta {
+            Ok(ref meta) if meta.file_type().is_symlink() => Err(Box::from(
+                format!("{:?} is a link, delete it before copying", maybe_path),
+            )),
+            Ok(ref meta) if !meta.is_dir() => {
+                Err(Box::from(ManageError::Conflict {
+                    path: maybe_path.to_owned(),
+                    target: target_path.to_owned(),
+                }))
+            }
+            _ => walk_target_dir(maybe_path, target_path, options),
+        };
+    }
+
+    let to_ret = match meta {
+        Err(_) => Some(SymLink::new_copy(maybe_path, target_path, false)),
+        Ok(ref meta) if meta.file_type().is_file() => {
+            Some(SymLink::new_copy(maybe_path, target_path, true))
+        }
+        // a link from before the file was copied is replaced
+        Ok(_) if link_points_to(maybe_path, target_path) => {
+            Some(SymLink::new_copy(maybe_path, target_path, false))
+        }
+        Ok(_) => conflict_check(maybe_path, target_path, options.on_conflict)?
+            .map(|sym| SymLink {
+                kind: LinkKind::Copy,
+                ..sym
+            }),
+    };
+
+    Ok(to_ret.into_iter().collect())
+}
+
+// the dir in under_dir a link points to when that is a dir of another
+// package, written from under_dir like our own targets
+fn other_package_dir(
+    maybe_path: &Path,
+    link_dest: &Path,
+    target_path: &Path,
+    options: &WalkOptions,
+) -> Option<PathBuf> {
+    let real_under = options
+        .under_dir
+        .canonicalize()
+        .unwrap_or_else(|_| options.under_dir.to_owned());
+
+    let full_dest = resolve_link(maybe_path, link_dest);
+    let relative = full_dest.strip_prefix(&real_under).ok()?;
+    let other_dir = options.under_dir.join(relative);
+
+    let is_other = other_dir.is_dir()
+        && target_path.is_dir()
+        && package_name(&other_dir, options.under_dir)
+            != package_name(target_path, options.under_dir);
+
+    if is_other {
+        Some(other_dir)
+    } else {
+        None
+    }
+}
+
+// another package has this dir linked, it becomes a real dir of links to
+// that package then our files go in next to them
+fn unfold_check(
+    maybe_path: &Path,
+    target_path: &Path,
+    other_dir: &Path,
+    options: &WalkOptions,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    let mut to_ret: Vec<SymLink> =
+        vec![SymLink::new_unfold(maybe_path, other_dir)];
+
+    let read_err = || ManageError::io_at("cant read", target_path);
+
+    for entry in fs::read_dir(target_path).map_err(read_err())? {
+        let entry = entry.map_err(read_err())?;
+        let target_child = entry.path();
+
+        if options.ignore.is_ignored(&target_child) {
+            continue;
+        }
+
+        let rendered = rendered_kind(&target_child);
+        let name = if rendered.is_some() {
+            rendered_name(&target_child).ok_or("cant get rendered name")?
+        } else {
+            target_child.file_name().ok_or("cant get file name")?
+        };
+
+        let upper_child = maybe_path.join(name);
+        let other_child = other_dir.join(name);
+
+        if other_child.symlink_metadata().is_ok() {
+            if rendered.is_some()
+                || !(other_child.is_dir() && target_child.is_dir())
+            {
+                return Err(Box::from(format!(
+                    "{:?} is in {:?} and {:?}",
+                    name, other_dir, target_path
+                )));
+            }
+
+            to_ret.append(&mut unfold_check(
+                &upper_child,
+                &target_child,
+                &other_child,
+                options,
+            )?);
+        } else if let Some(kind) = rendered {
+            to_ret.push(SymLink::new_rendered(
+                &upper_child,
+                &target_child,
+                false,
+                kind,
+            ));
+        } else if options.copy_rule.is_copy(&target_child) {
+            to_ret.append(&mut copy_check(
+                &upper_child,
+                &target_child,
+                options,
+            )?);
+        } else if !options.folding && target_child.is_dir() {
+            to_ret.append(&mut walk_target_dir(
+                &upper_child,
+                &target_child,
+                options,
+            )?);
+        } else {
+            to_ret.push(SymLink::new(&upper_child, &target_child, false));
+        }
+    }
+
+    Ok(to_ret)
+}
+
+// templates and secrets are written in to a real file named without their
+// extension instead of linked
+fn rendered_kind(target_path: &Path) -> Option<LinkKind> {
+    if is_template(target_path) {
+        Some(LinkKind::Template)
+    } else if is_secret(target_path) {
+        Some(LinkKind::Secret)
+    } else {
+        None
+    }
+}
+
+// a rendered file is a real file so a real file is what we expect to find,
+// walk_package checks it against the manifest before it is written over
+fn rendered_check(
+    upper_dir: &Path,
+    target_path: &Path,
+    kind: LinkKind,
+    on_conflict: &ConflictPolicy,
+) -> Result<Option<SymLink>, Box<dyn Error>> {
+    let name = rendered_name(target_path).ok_or("cant get rendered name")?;
+    let maybe_path = upper_dir.join(name);
+
+    match maybe_path.symlink_metadata() {
+        Err(_) => Ok(Some(SymLink::new_rendered(
+            &maybe_path,
+            target_path,
+            false,
+            kind,
+        ))),
+        Ok(meta) if meta.file_type().is_file() => Ok(Some(
+            SymLink::new_rendered(&maybe_path, target_path, true, kind),
+        )),
+        Ok(_) => Ok(conflict_check(&maybe_path, target_path, on_conflict)?
+            .map(|sym| SymLink { kind, ..sym })),
+    }
+}
+
+fn walk_target_dir(
+    upper_dir: &Path,
+    target_dir: &Path,
+    options: &WalkOptions,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    let on_conflict = options.on_conflict;
+
+    let read_err = || ManageError::io_at("cant read package", target_dir);
+
+    let read_target_dir = fs::read_dir(target_dir).map_err(read_err())?;
+
+    let mut to_ret: Vec<SymLink> = vec![];
+
+    for dir in read_target_dir {
+        let dir = dir.map_err(read_err())?;
+        let target_path = dir.path();
+        let target_file_name = dir.file_name();
+
+        if target_file_name == HOOKS_DIR
+            || target_file_name == IGNORE_FILE
+            || is_package_meta(&target_path, options.under_dir)
+        {
+            continue;
+        }
+
+        if options.ignore.is_ignored(&target_path) {
+            if options.report {
+                to_ret.push(SymLink {
+                    kind: LinkKind::Ignored,
+                    ..SymLink::new(
+                        &upper_dir.join(&target_file_name),
+                        &target_path,
+                        false,
+                    )
+                });
+            }
+
+            continue;
+        }
+
+        if let Some(kind) = rendered_kind(&target_path) {
+            if let Some(sym) =
+                rendered_check(upper_dir, &target_path, kind, on_conflict)?
+            {
+                to_ret.push(sym);
+            }
+
+            continue;
+        }
+
+        let maybe_path = upper_dir.join(&target_file_name);
+
+        if options.copy_rule.is_copy(&target_path) {
+            to_ret.append(&mut copy_check(&maybe_path, &target_path, options)?);
+            continue;
+        }
+
+        if let Ok(real_maybe) = maybe_path.read_link() {
+            if let Some(other_dir) = other_package_dir(
+                &maybe_path,
+                &real_maybe,
+                &target_path,
+                options,
+            ) {
+                to_ret.append(&mut unfold_check(
+                    &maybe_path,
+                    &target_path,
+                    &other_dir,
+                    options,
+                )?);
+                continue;
+            }
+
+            let sym =
+                match symlink_check(&real_maybe, &maybe_path, &target_path) {
+                    Ok(sym) => sym,
+                    // when remaking any link in to under_dir can be replaced
+                    Err(err) => match options.remake_under {
+                        Some(under)
+                            if resolve_link(&maybe_path, &real_maybe)
+                                .starts_with(under) =>
+                        {
+                            SymLink::new(&maybe_path, &target_path, true)
+                        }
+                        _ if options.report => {
+                            SymLink::new_conflict(&maybe_path, &target_path)
+                        }
+                        _ => return Err(err),
+                    },
+                };
+
+            to_ret.push(sym);
+        } else if maybe_path.is_dir() {
+            match walk_target_dir(&maybe_path, &target_path, options) {
+                Ok(mut new_to_ret) => to_ret.append(&mut new_to_ret),
+                Err(err) => return Err(err),
+            };
+        } else if !maybe_path.exists() {
+            if !options.folding && target_path.is_dir() {
+                to_ret.append(&mut walk_target_dir(
+                    &maybe_path,
+                    &target_path,
+                    options,
+                )?);
+            } else {
+                to_ret.push(SymLink::new(&maybe_path, &target_path, false));
+            }
+        } else if let Some(sym) =
+            conflict_check(&maybe_path, &target_path, on_conflict)?
+        {
+            to_ret.push(sym);
+        }
+    }
+
+    Ok(to_ret)
+}
+
+pub fn get_symlink_vec(
+    upper_dir: &Path,
+    target_dir: &Path,
+    on_conflict: &ConflictPolicy,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    let options = WalkOptions {
+        on_conflict,
+        remake_under: None,
+        copy_rule: &CopyRule::default(),
+        ignore: &IgnoreRules::default(),
+        under_dir: target_dir.parent().unwrap_or(target_dir),
+        folding: true,
+        report: false,
+    };
+
+    walk_target_dir(upper_dir, target_dir, &options)
+}
+
+// walk a target dir with every option from main_app, copies get the checksum
+// they were deployed with and real files we did not copy are conflicts
+fn get_deploy_vec(
+    main_app: &MainApp,
+    target_dir: &Path,
+    manifest: &Manifest,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    let options = WalkOptions {
+        on_conflict: &main_app.on_conflict,
+        remake_under: if main_app.action == Action::Remake {
+            Some(&main_app.under_dir)
+        } else {
+            None
+        },
+        copy_rule: &main_app.copy_rule,
+        ignore: &main_app.ignore,
+        under_dir: &main_app.under_dir,
+        folding: main_app.folds(&package_name(target_dir, &main_app.under_dir)),
+        report: false,
+    };
+
+    walk_package(main_app, target_dir, manifest, &options)
+}
+
+// the walk link would do with every conflict and stray link kept as a
+// conflict so status gives the same answer as link
+pub fn get_status_vec(
+    main_app: &MainApp,
+    target_dir: &Path,
+    manifest: &Manifest,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    let options = WalkOptions {
+        on_conflict: &ConflictPolicy::Backup,
+        remake_under: None,
+        copy_rule: &main_app.copy_rule,
+        ignore: &main_app.ignore,
+        under_dir: &main_app.under_dir,
+        folding: main_app.folds(&package_name(target_dir, &main_app.under_dir)),
+        report: true,
+    };
+
+    walk_package(main_app, target_dir, manifest, &options)
+}
+
+fn walk_package(
+    main_app: &MainApp,
+    target_dir: &Path,
+    manifest: &Manifest,
+    options: &WalkOptions,
+) -> Result<Vec<SymLink>, Box<dyn Error>> {
+    // the package then each @NAME dir in it against their own roots
+    let mut walked: Vec<SymLink> = vec![];
+    for (root, dir) in main_app.roots.package_dirs(target_dir)? {
+        walked.append(&mut walk_target_dir(&root, &dir, options)?);
+    }
+
+    let mut to_ret: Vec<SymLink> = vec![];
+
+    for mut sym in walked {
+        let written = matches!(
+            sym.kind,
+            LinkKind::Copy | LinkKind::Template | LinkKind::Secret
+        );
+
+        if written && sym.exists {
+            sym.deployed = manifest.checksum(&sym.upper_file);
+
+            // a copy that matches its target is ours, a rendered file only
+            // when it is what the manifest says we wrote
+            let owned = match sym.kind {
+                LinkKind::Copy => {
+                    sym.deployed.is_some()
+                        || is_deployed(&sym.upper_file, &sym.target_file)
+                }
+                _ => sym.deployed.is_some() && !sym.drifted()?,
+            };
+
+            if !owned {
+                if let Some(conflict) = conflict_check(
+                    &sym.upper_file,
+                    &sym.target_file,
+                    options.on_conflict,
+                )? {
+                    to_ret.push(SymLink {
+                        kind: sym.kind,
+                        ..conflict
+                    });
+                }
+
+                continue;
+            }
+        }
+
+        to_ret.push(sym);
+    }
+
+    Ok(to_ret)
+}
+
+// find the links in to under_dir that the manifest has and the ones next to
+// where the packages link to, the rest of upper_dir is never walked
+pub fn find_owned_links<'a, I>(
+    under_dir: &Path,
+    manifest: &Manifest,
+    syms: I,
+) -> Vec<SymLink>
+where
+    I: IntoIterator<Item = &'a SymLink>,
+{
+    let mut dirs: Vec<PathBuf> = syms
+        .into_iter()
+        .filter_map(|sym| sym.upper_file.parent().map(Path::to_owned))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+
+    let mut paths: Vec<PathBuf> = manifest
+        .links
+        .iter()
+        .map(|entry| entry.link.clone())
+        .collect();
+
+    for dir in &dirs {
+        // dirs we cant read cant hold our links
+        if let Ok(read_dir) = fs::read_dir(dir) {
+            paths.extend(read_dir.flatten().map(|entry| entry.path()));
+        }
+    }
+
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .iter()
+        .filter_map(|path| {
+            let full_dest = resolve_link(path, &path.read_link().ok()?);
+
+            if full_dest.starts_with(under_dir) {
+                Some(SymLink::new(path, &full_dest, true))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// remove owned links that are not part of the current target set
+fn remove_stale_links(
+    stale_links: &[SymLink],
+    main_app: &MainApp,
+) -> WorkResult {
+    if stale_links.is_empty() {
+        return Ok(false);
+    }
+
+    let mut trans = Transaction::default();
+
+    for sym in stale_links {
+        let work = if main_app.dry_run {
+            info!("would remove stale {}", sym);
+            Ok(true)
+        } else {
+            sym.delete(main_app, &mut trans)
+        };
+
+        report_link(sym, main_app, "remove stale", &work);
+
+        if let Err(err) = work {
+            return Err(rollback_error(err, trans));
+        }
+    }
+
+    trans.commit()?;
+
+    trace!("{}", vec_to_string("removed stale", stale_links));
+
+    Ok(true)
+}
+
+// the dir in under_dir an unfolded dir can be a link to again, every thing
+// in it has to be a link to the same dir and every thing in that dir linked
+fn fold_dest(dir: &Path, main_app: &MainApp) -> Option<PathBuf> {
+    let mut dest_dir: Option<PathBuf> = None;
+
+    for entry in fs::read_dir(dir).ok()? {
+        let entry_path = entry.ok()?.path();
+        let link_dest =
+            resolve_link(&entry_path, &entry_path.read_link().ok()?);
+
+        if link_dest.file_name() != entry_path.file_name() {
+            return None;
+        }
+
+        let parent = link_dest.parent()?.to_owned();
+        match &dest_dir {
+            Some(dest) if *dest != parent => return None,
+            Some(_) => {}
+            None => dest_dir = Some(parent),
+        }
+    }
+
+    let dest_dir = dest_dir?;
+
+    let real_under = main_app.under_dir.canonicalize().ok()?;
+    let dest_dir = main_app
+        .under_dir
+        .join(dest_dir.strip_prefix(&real_under).ok()?);
+
+    if !main_app.folds(&package_name(&dest_dir, &main_app.under_dir)) {
+        return None;
+    }
+
+    let all_linked = fs::read_dir(&dest_dir).ok()?.flatten().all(|entry| {
+        main_app.ignore.is_ignored(&entry.path())
+            || dir.join(entry.file_name()).symlink_metadata().is_ok()
+    });
+
+    if all_linked {
+        Some(dest_dir)
+    } else {
+        None
+    }
+}
+
+// swap a dir of links for one link to the dir they point in to
+fn fold_dir(
+    dir: &Path,
+    dest_dir: &Path,
+    main_app: &MainApp,
+    trans: &mut Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let fold_err = || ManageError::io_at("cant fold", dir);
+
+    for entry in fs::read_dir(dir).map_err(fold_err())? {
+        let entry_path = entry.map_err(fold_err())?.path();
+        let link_dest = entry_path
+            .read_link()
+            .map_err(ManageError::io_at("cant fold", &entry_path))?;
+
+        fs::remove_file(&entry_path)
+            .map_err(ManageError::io_at("cant fold", &entry_path))?;
+        trans.removed(&entry_path, &link_dest);
+    }
+
+    fs::remove_dir(dir).map_err(fold_err())?;
+    trans.removed_dir(dir);
+
+    SymLink::new(dir, dest_dir, false)
+        .make_symlink(&main_app.link_style, trans)?;
+
+    Ok(())
+}
+
+// fold dirs that were unfolded for a package that is gone back in to one
+// link, deepest first so a folded dir can let the dir above it fold
+fn fold_dirs(main_app: &MainApp, manifest: &mut Manifest) -> WorkResult {
+    let mut unfolded: Vec<PathBuf> = manifest.unfolded.clone();
+    unfolded.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    let commit = head_commit_id(&main_app.under_dir);
+    let mut trans = Transaction::default();
+    let mut did_work = false;
+
+    for dir in unfolded {
+        let is_real_dir = dir
+            .symlink_metadata()
+            .map(|meta| meta.is_dir())
+            .unwrap_or(false);
+
+        if !is_real_dir && !main_app.dry_run {
+            manifest.remove_unfolded(&dir);
+            continue;
+        }
+
+        let dest_dir = match fold_dest(&dir, main_app) {
+            Some(dest_dir) => dest_dir,
+            None => continue,
+        };
+
+        did_work = true;
+
+        if main_app.dry_run {
+            info!("would fold {:?} -> {:?}", dir, dest_dir);
+            continue;
+        }
+
+        if let Err(err) = fold_dir(&dir, &dest_dir, main_app, &mut trans) {
+            return Err(rollback_error(err, trans));
+        }
+
+        manifest
+            .links
+            .retain(|entry| entry.link.parent() != Some(dir.as_path()));
+        manifest.remove_unfolded(&dir);
+        manifest.record(ManifestEntry {
+            link: dir.clone(),
+            target: dest_dir.clone(),
+            package: package_name(&dest_dir, &main_app.under_dir),
+            commit: commit.clone(),
+            checksum: None,
+        });
+    }
+
+    trans.commit()?;
+
+    Ok(did_work)
+}
+
+// a package that cant be deployed does not stop the rest, the first error is
+// returned once they are done and any later ones are printed
+fn keep_first_error(failed: &mut Option<Box<dyn Error>>, err: Box<dyn Error>) {
+    if failed.is_some() {
+        error!("Symlink Error {}", err);
+    } else {
+        *failed = Some(err);
+    }
+}
+
+// run the hooks around one package, a failing hook stops the run but a
+// package that fails to link is kept in failed and the others still linked
+fn link_package(
+    main_app: &MainApp,
+    target_dir: &Path,
+    syms: &[SymLink],
+    manifest: &mut Manifest,
+    commit: &str,
+    failed: &mut Option<Box<dyn Error>>,
+) -> WorkResult {
+    run_hook(main_app, target_dir, HookKind::PreLink)?;
+
+    let ran = run_sym_vec(syms, main_app);
+
+    // record what was made even when a later link failed
+    record_links(manifest, syms, main_app, commit);
+
+    let did_work = match ran {
+        Ok(did_work) => did_work,
+        Err(err) => {
+            keep_first_error(failed, err);
+            return Ok(false);
+        }
+    };
+
+    run_hook(main_app, target_dir, HookKind::PostLink)?;
+
+    Ok(did_work)
+}
+
+fn remake_symlinks(main_app: &MainApp, manifest: &mut Manifest) -> WorkResult {
+    let under_dir: &PathBuf = &main_app.under_dir;
+
+    let mut failed: Option<Box<dyn Error>> = None;
+    let mut sym_vecs: Vec<(&PathBuf, Vec<SymLink>)> = vec![];
+    for target_dir in &main_app.target_dirs {
+        match get_deploy_vec(main_app, target_dir, manifest) {
+            Ok(syms) => sym_vecs.push((target_dir, syms)),
+            Err(err) => keep_first_error(&mut failed, err),
+        }
+    }
+
+    let is_wanted = |link: &Path| {
+        sym_vecs
+            .iter()
+            .flat_map(|(_, syms)| syms)
+            .any(|sym| sym.upper_file == link)
+    };
+
+    // links from the manifest first then our links next to the packages
+    let mut stale_links: Vec<SymLink> = selected_entries(manifest, main_app)
+        .into_iter()
+        .filter(|entry| {
+            entry.is_intact()
+                && !is_wanted(&entry.link)
+                && !main_app.ignore.is_ignored(&entry.target)
+        })
+        .map(entry_symlink)
+        .collect();
+
+    let walked = sym_vecs.iter().flat_map(|(_, syms)| syms);
+
+    for owned in find_owned_links(under_dir, manifest, walked) {
+        let known = stale_links
+            .iter()
+            .any(|stale| stale.upper_file == owned.upper_file);
+
+        // links to ignored paths are left alone
+        let ignored = main_app.ignore.is_ignored(&owned.target_file);
+
+        if !known && !ignored && !is_wanted(&owned.upper_file) {
+            stale_links.push(owned);
+        }
+    }
+
+    let mut did_work = remove_stale_links(&stale_links, main_app)?;
+
+    if !main_app.dry_run {
+        for stale in &stale_links {
+            manifest.remove(&stale.upper_file);
+        }
+    }
+
+    let commit = head_commit_id(under_dir);
+    for (target_dir, old_syms) in &sym_vecs {
+        // an earlier package can have unfolded a dir this one uses
+        let syms = if main_app.dry_run {
+            Ok(old_syms.to_vec())
+        } else {
+            get_deploy_vec(main_app, target_dir, manifest)
+        };
+
+        let syms = match syms {
+            Ok(syms) => syms,
+            Err(err) => {
+                keep_first_error(&mut failed, err);
+                continue;
+            }
+        };
+
+        if link_package(
+            main_app,
+            target_dir,
+            &syms,
+            manifest,
+            &commit,
+            &mut failed,
+        )? {
+            did_work = true;
+        }
+    }
+
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(did_work),
+    }
+}
+
+// remove the links the manifest knows about then any left in the current
+// target dirs from before there was a manifest
+fn delete_symlinks(main_app: &MainApp, manifest: &mut Manifest) -> WorkResult {
+    let selected: Vec<ManifestEntry> = selected_entries(manifest, main_app)
+        .into_iter()
+        .cloned()
+        .collect();
+
+    let to_delete: Vec<SymLink> = selected
+        .iter()
+        .filter(|entry| entry.is_intact())
+        .map(entry_symlink)
+        .collect();
+
+    run_hooks(main_app, &main_app.target_dirs, HookKind::PreUnlink)?;
+
+    let mut did_work = run_sym_vec(&to_delete, main_app)?;
+
+    if !main_app.dry_run {
+        for entry in &selected {
+            manifest.remove(&entry.link);
+        }
+    }
+
+    for target_dir in &main_app.target_dirs {
+        let syms = match get_deploy_vec(main_app, target_dir, manifest) {
+            Ok(syms) => syms,
+            Err(err) => {
+                error!("Symlink Error {}", err);
+                continue;
+            }
+        };
+
+        let not_in_manifest: Vec<SymLink> = syms
+            .into_iter()
+            .filter(|sym| {
+                !to_delete.iter().any(|del| del.upper_file == sym.upper_file)
+            })
+            .collect();
+
+        if run_sym_vec(&not_in_manifest, main_app)? {
+            did_work = true;
+        }
+    }
+
+    if fold_dirs(main_app, manifest)? {
+        did_work = true;
+    }
+
+    run_hooks(main_app, &main_app.target_dirs, HookKind::PostUnlink)?;
+
+    Ok(did_work)
+}
+
+fn make_symlinks(main_app: &MainApp, manifest: &mut Manifest) -> WorkResult {
+    let commit = head_commit_id(&main_app.under_dir);
+
+    let mut failed: Option<Box<dyn Error>> = None;
+    let mut did_work = false;
+    for target_dir in &main_app.target_dirs {
+        match get_deploy_vec(main_app, target_dir, manifest) {
+            Ok(syms) => {
+                if link_package(
+                    main_app,
+                    target_dir,
+                    &syms,
+                    manifest,
+                    &commit,
+                    &mut failed,
+                )? {
+                    did_work = true;
+                }
+            }
+            Err(err) => keep_first_error(&mut failed, err),
+        }
+    }
+
+    // the other packages are still linked but the exit code shows the
+    // package that failed
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(did_work),
+    }
+}
+
+// move each path in to the package at the same place under the package
+// root then link it back, if any path fails the ones before it are put back
+fn add_paths(main_app: &MainApp, manifest: &mut Manifest) -> WorkResult {
+    let package_dir =
+        main_app.target_dirs.first().ok_or("no package to add to")?;
+
+    let root = main_app.roots.package_root(package_dir)?;
+
+    let mut to_sym_vec: Vec<SymLink> = vec![];
+    for path in &main_app.to_add {
+        let relative = path
+            .strip_prefix(&root)
+            .map_err(|_| format!("{:?} is not in {:?}", path, root))?;
+
+        if path.symlink_metadata().is_err() {
+            return Err(Box::from(format!("{:?} does not exist", path)));
+        }
 
-    for dir in read_target_dir {
-        let dir = dir.expect("didn't get dir?");
-        let target_path = dir.path();
-        let target_file_name = dir.file_name();
+        if path.read_link().is_ok() {
+            return Err(Box::from(format!("{:?} is already a link", path)));
+        }
 
-        let maybe_path = upper_dir.join(&target_file_name);
+        let target_path = package_dir.join(relative);
 
-        if let Ok(real_maybe) = maybe_path.read_link() {
-            let sym = symlink_check(
-                &real_maybe,
-                &maybe_path,
-                &target_dir,
-                &upper_dir,
-            )?;
+        if target_path.symlink_metadata().is_ok() {
+            return Err(Box::from(format!(
+                "{:?} is already in {:?}",
+                relative, package_dir
+            )));
+        }
 
-            to_ret.push(sym);
-        } else if maybe_path.is_dir() {
-            match get_symlink_vec(&maybe_path, &target_path) {
-                Ok(mut new_to_ret) => to_ret.append(&mut new_to_ret),
-                Err(err) => return Err(err),
-            };
-        } else if !maybe_path.exists() {
-            to_ret.push(SymLink::new(&maybe_path, &target_path, false));
-        } else {
-            return Err(Box::from(format!("file exists {:?}", maybe_path)));
+        to_sym_vec.push(SymLink::new(path, &target_path, false));
+    }
+
+    if main_app.dry_run {
+        for sym in &to_sym_vec {
+            info!("would add {}", sym);
+            report_link(sym, main_app, "add", &Ok(true));
         }
+
+        return Ok(!to_sym_vec.is_empty());
     }
 
-    Ok(to_ret)
+    let mut trans = Transaction::default();
+
+    for sym in &to_sym_vec {
+        let added = make_parent_dirs(&sym.target_file, &mut trans)
+            .and_then(|_| move_path(&sym.upper_file, &sym.target_file))
+            .and_then(|_| {
+                trans.moved(&sym.upper_file, &sym.target_file, true);
+                sym.make_symlink(&main_app.link_style, &mut trans)
+            });
+
+        report_link(sym, main_app, "add", &added);
+
+        if let Err(err) = added {
+            return Err(rollback_error(err, trans));
+        }
+    }
+
+    trans.commit()?;
+
+    let commit = head_commit_id(&main_app.under_dir);
+    record_links(manifest, &to_sym_vec, main_app, &commit);
+
+    trace!("{}", vec_to_string("added", &to_sym_vec));
+
+    Ok(!to_sym_vec.is_empty())
 }
 
-pub fn manage_symlinks(main_app: &MainApp) -> Result<(), Box<dyn Error>> {
-    set_current_dir("/").expect("cant change dir");
+pub fn manage_symlinks(main_app: &MainApp) -> Result<(), ManageError> {
+    // sync only touches the repo
+    if main_app.action == Action::Sync {
+        return Ok(());
+    }
 
-    let target_dirs: &Vec<PathBuf> = &main_app.target_dirs;
-    let upper_dir: &PathBuf = &main_app.upper_dir;
+    set_current_dir("/")
+        .map_err(ManageError::io_at("cant change to", Path::new("/")))?;
+
+    let mut manifest = Manifest::load(&main_app.manifest_file)?;
+
+    let did_work = match main_app.action {
+        Action::Make => make_symlinks(main_app, &mut manifest),
+        Action::Delete => delete_symlinks(main_app, &mut manifest),
+        Action::Remake => remake_symlinks(main_app, &mut manifest),
+        Action::Status => print_status(main_app, &manifest).map(|_| false),
+        Action::Restore => match main_app.backup_dir.parent() {
+            Some(backup_root) => restore_backups(
+                &main_app.upper_dir,
+                backup_root,
+                main_app.dry_run,
+            ),
+            None => Err(Box::from("cant get backup root")),
+        },
+        Action::Add => add_paths(main_app, &mut manifest),
+        Action::Sync => Ok(false),
+        Action::Doctor
+        | Action::Encrypt
+        | Action::EditSecret
+        | Action::Init => Err(Box::from("bad action")),
+    };
 
-    let map_low_to_sym_vec = target_dirs
-        .iter()
-        .map(|l_d| get_symlink_vec(upper_dir, l_d));
+    // save before checking for errors so links that were made are kept
+    let changes_links = matches!(
+        main_app.action,
+        Action::Make | Action::Delete | Action::Remake | Action::Add
+    );
 
-    let mut did_work = false;
-    for sym_vec in map_low_to_sym_vec {
-        match sym_vec {
-            // this is probably unnecessary
-            Ok(ref syms) => {
-                if run_sym_vec(syms, main_app)? {
-                    did_work = true;
-                }
-            }
-            Err(err) => eprintln!("Symlink Error {}", err),
-        }
+    if changes_links && !main_app.dry_run {
+        manifest.save(&main_app.manifest_file)?;
     }
 
-    if did_work {
-        main_app.verbose_ouput(&format!("{} link[s]", main_app.action), None);
+    if did_work? {
+        debug!("{} link[s]", main_app.action);
     } else {
-        main_app.verbose_ouput("nothing to do", None);
+        debug!("nothing to do");
     }
 
     Ok(())
@@ -230,14 +2072,20 @@ pub fn manage_symlinks(main_app: &MainApp) -> Result<(), Box<dyn Error>> {
 mod test {
     // thanks https://medium.com/@ericdreichert/
     //       test-setup-and-teardown-in-rust-without-a-framework-ba32d97aa5ab
+    use std::collections::BTreeMap;
     use std::fs;
     use std::os::unix;
+    use std::os::unix::fs::PermissionsExt;
     use std::panic;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     use tempfile::tempdir;
 
     use super::*;
+    use crate::hook_utils::HookConf;
+    use crate::ignore_utils::IGNORE_FILE;
+    use crate::root_utils::DestRoots;
+    use crate::secret_utils::{encrypt_file, SECRET_MODE};
 
     struct TestData {
         dir_vec: Vec<PathBuf>,
@@ -262,7 +2110,7 @@ mod test {
     // TODO: find edge cases
     fn setup_temp<Y, T>(fake_data: Y, test: T)
     where
-        Y: FnOnce(&PathBuf) -> TestData,
+        Y: FnOnce(&Path) -> TestData,
         T: FnOnce(&PathBuf, &PathBuf),
     {
         let test_dir = tempdir().unwrap();
@@ -275,247 +2123,1143 @@ mod test {
         let files_to_make = data.file_vec;
         let dirs_to_make = data.dir_vec;
 
-        for dir in dirs_to_make {
-            if let Err(err) = fs::create_dir_all(&dir) {
-                assert!(false, "dir {:?} make error {}", dir, err);
+        for dir in dirs_to_make {
+            if let Err(err) = fs::create_dir_all(&dir) {
+                panic!("dir {:?} make error {}", dir, err);
+            }
+        }
+
+        for file in files_to_make {
+            if let Err(err) = fs::File::create(&file) {
+                panic!("file {:?} make error {}", file, err);
+            }
+        }
+
+        assert!(fake_path.exists(), "tempdir fails");
+
+        test(&temp_path, &fake_path);
+    }
+
+    fn fake_zsh_data(system_dir: &Path) -> TestData {
+        let dirs_to_make: Vec<PathBuf> = ["zshrc.d", ".under/fake_zsh/zshrc.d"]
+            .iter()
+            .map(|dir| system_dir.join(dir))
+            .collect();
+
+        let files_to_make: Vec<PathBuf> = [
+            ".under/fake_zsh/zshrc",
+            ".under/fake_zsh/zshrc.d/zshrc_conf",
+        ]
+        .iter()
+        .map(|file| system_dir.join(file))
+        .collect();
+
+        let fake_path = system_dir.join(".under/fake_zsh");
+
+        TestData::new(dirs_to_make, files_to_make, fake_path)
+    }
+
+    fn hand_made_zsh_data(system_path: &Path) -> Vec<PathBuf> {
+        ["zshrc.d/zshrc_conf", "zshrc"]
+            .iter()
+            .map(|file| system_path.join(file))
+            .collect()
+    }
+
+    fn fake_i3_data(system_dir: &Path) -> TestData {
+        let dirs_to_make: Vec<PathBuf> =
+            [".config", ".under/fake_i3/.config/i3"]
+                .iter()
+                .map(|dir| system_dir.join(dir))
+                .collect();
+
+        let files_to_make: Vec<PathBuf> = [".under/fake_i3/.config/i3/i3_conf"]
+            .iter()
+            .map(|file| system_dir.join(file))
+            .collect();
+
+        let fake_path = system_dir.join(".under/fake_i3");
+
+        TestData::new(dirs_to_make, files_to_make, fake_path)
+    }
+
+    fn hand_made_i3_data(temp_path: &Path) -> Vec<PathBuf> {
+        [".config/i3"]
+            .iter()
+            .map(|file| temp_path.join(file))
+            .collect()
+    }
+
+    #[test]
+    fn test_get_symlink_paths_fake_zsh() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let hand_test_zsh: Vec<PathBuf> = hand_made_zsh_data(temp_path);
+
+            let to_sym = match get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &ConflictPolicy::Abort,
+            ) {
+                Ok(sym) => sym,
+                Err(err) => {
+                    panic!("failed to get_symlink_paths {}", err);
+                }
+            };
+
+            let flat_syms: Vec<PathBuf> = to_sym
+                .iter()
+                .flat_map(|sym| {
+                    vec![sym.upper_file.to_owned(), sym.target_file.to_owned()]
+                })
+                .collect();
+
+            for hand in hand_test_zsh {
+                assert!(
+                    flat_syms.contains(&hand),
+                    "hand made sym {:?} not found",
+                    hand
+                );
+            }
+        })
+    }
+
+    #[test]
+    fn test_get_symlink_paths_fake_i3() {
+        setup_temp(fake_i3_data, |temp_path, fake_under_i3| {
+            let hand_test_links: Vec<PathBuf> = hand_made_i3_data(temp_path);
+
+            let to_sym = match get_symlink_vec(
+                temp_path,
+                fake_under_i3,
+                &ConflictPolicy::Abort,
+            ) {
+                Ok(sym) => sym,
+                Err(err) => {
+                    panic!("failed to get_symlink_paths shit {}", err);
+                }
+            };
+
+            let flat_syms: Vec<PathBuf> = to_sym
+                .iter()
+                .flat_map(|sym| {
+                    vec![sym.upper_file.to_owned(), sym.target_file.to_owned()]
+                })
+                .collect();
+
+            for hand in &hand_test_links {
+                assert!(
+                    flat_syms.contains(hand),
+                    "hand made sym {:?} not found",
+                    hand
+                );
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_sym_zsh() {
+        setup_temp(fake_zsh_data, |temp_path, _| {
+            let syms = [
+                SymLink::new(
+                    &temp_path.join("zshrc"),
+                    &temp_path.join(".under/fake_zsh/zshrc"),
+                    false,
+                ),
+                SymLink::new(
+                    &temp_path.join("zshrc.d/zshrc_conf"),
+                    &temp_path.join(".under/fake_zsh/zshrc.d/zshrc_conf"),
+                    false,
+                ),
+            ];
+
+            let main = MainApp::fake(temp_path, Action::Make);
+
+            if let Err(err) = run_sym_vec(&syms, &main) {
+                panic!("cant make symlink {}", err)
+            };
+
+            for sym in syms {
+                assert!(sym.upper_file.exists(), "didn't make symlink");
+
+                let up_conz = sym.upper_file.canonicalize().unwrap();
+                let up_str = up_conz.as_os_str();
+
+                let lo_str = sym.target_file.as_os_str();
+
+                assert!(up_str == lo_str, "not pointing to right place");
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_sym_i3() {
+        setup_temp(fake_i3_data, |temp_path, _| {
+            let syms = [SymLink::new(
+                &temp_path.join(".config/i3"),
+                &temp_path.join(".under/fake_i3/.config/i3"),
+                false,
+            )];
+
+            let main = MainApp::fake(temp_path, Action::Make);
+
+            if let Err(err) = run_sym_vec(&syms, &main) {
+                panic!("cant make symlink {}", err)
+            };
+
+            for sym in syms {
+                assert!(sym.upper_file.exists(), "didnt make symlink");
+
+                let up_conz = sym.upper_file.canonicalize().unwrap();
+                let up_str = up_conz.as_os_str();
+
+                let lo_str = sym.target_file.as_os_str();
+
+                assert!(up_str == lo_str, "not pointing to right place");
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_delete_symlink_zsh() {
+        setup_temp(fake_zsh_data, |temp_path, _| {
+            let syms = [
+                SymLink::new(
+                    &temp_path.join("zshrc.d/zshrc_conf"),
+                    &temp_path.join(".under/fake_zsh/zshrc.d/zshrc_conf"),
+                    true,
+                ),
+                SymLink::new(
+                    &temp_path.join("zshrc"),
+                    &temp_path.join(".under/fake_zsh/zshrc"),
+                    true,
+                ),
+            ];
+
+            let main = MainApp::fake(temp_path, Action::Delete);
+
+            for sym in &syms {
+                assert!(!sym.upper_file.exists(), "file exists?");
+
+                unix::fs::symlink(&sym.target_file, &sym.upper_file).unwrap();
+
+                assert!(sym.upper_file.exists(), "didn't make sym");
+            }
+
+            if let Err(err) = run_sym_vec(&syms, &main) {
+                panic!("cant delete symlink {}", err)
+            };
+
+            for sym in syms {
+                assert!(!sym.upper_file.exists(), "didn't remove symlink");
+            }
+        })
+    }
+
+    #[test]
+    fn test_get_symlink_vec_existing_link() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let upper_file = temp_path.join("zshrc");
+            let target_file = temp_path.join(".under/fake_zsh/zshrc");
+
+            unix::fs::symlink(&target_file, &upper_file).unwrap();
+
+            let to_sym = match get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &ConflictPolicy::Abort,
+            ) {
+                Ok(sym) => sym,
+                Err(err) => panic!("failed to get_symlink_vec {}", err),
+            };
+
+            let found = to_sym
+                .iter()
+                .find(|sym| sym.upper_file == upper_file)
+                .expect("didn't find existing link");
+
+            assert!(found.exists, "existing link not marked as existing");
+        })
+    }
+
+    #[test]
+    fn test_make_relative_symlink() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.link_style = LinkStyle::Relative;
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make relative links {}", err);
+            }
+
+            let upper_file = temp_path.join("zshrc.d/zshrc_conf");
+            assert_eq!(
+                upper_file.read_link().unwrap(),
+                Path::new("../.under/fake_zsh/zshrc.d/zshrc_conf")
+            );
+
+            let to_sym = match get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &ConflictPolicy::Abort,
+            ) {
+                Ok(sym) => sym,
+                Err(err) => panic!("relative link not owned {}", err),
+            };
+
+            assert!(to_sym.iter().all(|sym| sym.exists));
+        })
+    }
+
+    #[test]
+    fn test_link_hooks() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let hooks_dir = fake_under_zsh.join(HOOKS_DIR);
+            let hook_out = temp_path.join("hook_out");
+
+            fs::create_dir_all(&hooks_dir).unwrap();
+            fs::write(hooks_dir.join("post_link"), "fake").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+            main.hooks.insert(
+                "fake_zsh".to_string(),
+                HookConf {
+                    post_link: Some(format!(
+                        "echo $MANAGE_HOOK $MANAGE_ACTION $MANAGE_PACKAGE > {}",
+                        hook_out.display()
+                    )),
+                    ..HookConf::default()
+                },
+            );
+
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
+
+            assert_eq!(
+                fs::read_to_string(&hook_out).unwrap(),
+                "post_link make fake_zsh\n"
+            );
+            assert!(
+                temp_path.join(HOOKS_DIR).symlink_metadata().is_err(),
+                "hooks dir was linked"
+            );
+
+            // a failing hook stops before anything is removed
+            main.action = Action::Delete;
+            main.hooks.get_mut("fake_zsh").unwrap().pre_unlink =
+                Some("exit 3".to_string());
+
+            match delete_symlinks(&main, &mut manifest) {
+                Ok(_) => panic!("failed hook didn't stop delete"),
+                Err(err) => assert_eq!(
+                    format!("{}", err),
+                    "pre_unlink hook for fake_zsh failed with exit status: 3"
+                ),
             }
-        }
 
-        for file in files_to_make {
-            if let Err(err) = fs::File::create(&file) {
-                assert!(false, "file {:?} make error {}", file, err);
+            assert!(temp_path.join("zshrc").read_link().is_ok());
+        })
+    }
+
+    #[test]
+    fn test_link_hook_failure_stops() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let fake_under_vim = temp_path.join(".under/fake_vim");
+            fs::create_dir_all(&fake_under_vim).unwrap();
+            fs::write(fake_under_vim.join("vimrc"), "fake").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned(), fake_under_vim];
+            main.hooks.insert(
+                "fake_zsh".to_string(),
+                HookConf {
+                    pre_link: Some("exit 3".to_string()),
+                    ..HookConf::default()
+                },
+            );
+
+            // nothing after the failed hook is linked
+            match make_symlinks(&main, &mut Manifest::default()) {
+                Ok(_) => panic!("failed hook wasn't returned"),
+                Err(err) => assert_eq!(
+                    format!("{}", err),
+                    "pre_link hook for fake_zsh failed with exit status: 3"
+                ),
             }
-        }
 
-        assert!(fake_path.exists(), "tempdir fails");
+            assert!(temp_path.join("zshrc").symlink_metadata().is_err());
+            assert!(temp_path.join("vimrc").symlink_metadata().is_err());
 
-        test(&temp_path, &fake_path);
+            // a package that fails to link does not stop the next one
+            main.hooks.clear();
+            fs::write(temp_path.join("zshrc"), "real").unwrap();
 
-        assert!(true, "idk how this would fail");
+            assert!(
+                make_symlinks(&main, &mut Manifest::default()).is_err(),
+                "failed package wasn't returned"
+            );
+            assert!(temp_path.join("vimrc").read_link().is_ok());
+        })
     }
 
-    fn fake_zsh_data(system_dir: &PathBuf) -> TestData {
-        let dirs_to_make: Vec<PathBuf> =
-            vec!["zshrc.d", ".under/fake_zsh/zshrc.d"]
-                .iter()
-                .map(|dir| system_dir.join(dir))
-                .collect();
+    #[test]
+    fn test_ignored_paths_not_linked() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            fs::write(fake_under_zsh.join(IGNORE_FILE), "zshrc.d/\n").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.ignore = IgnoreRules::new(&main.under_dir, &[]).unwrap();
+
+            let syms = match get_deploy_vec(
+                &main,
+                fake_under_zsh,
+                &Manifest::default(),
+            ) {
+                Ok(syms) => syms,
+                Err(err) => panic!("cant get deploy vec {}", err),
+            };
 
-        let files_to_make: Vec<PathBuf> = vec![
-            ".under/fake_zsh/zshrc",
-            ".under/fake_zsh/zshrc.d/zshrc_conf",
+            let upper_files: Vec<&PathBuf> =
+                syms.iter().map(|sym| &sym.upper_file).collect();
+
+            assert_eq!(upper_files, vec![&temp_path.join("zshrc")]);
+        })
+    }
+
+    fn fake_i3_pair_data(system_dir: &Path) -> TestData {
+        let dirs_to_make: Vec<PathBuf> = [
+            ".config",
+            ".under/i3_all/.config/i3",
+            ".under/i3_M/.config/i3",
+        ]
+        .iter()
+        .map(|dir| system_dir.join(dir))
+        .collect();
+
+        let files_to_make: Vec<PathBuf> = [
+            ".under/i3_all/.config/i3/config",
+            ".under/i3_M/.config/i3/monitor",
         ]
         .iter()
         .map(|file| system_dir.join(file))
         .collect();
 
-        let fake_path = system_dir.join(".under/fake_zsh");
+        let fake_path = system_dir.join(".under");
 
         TestData::new(dirs_to_make, files_to_make, fake_path)
     }
 
-    fn hand_made_zsh_data(system_path: &PathBuf) -> Vec<PathBuf> {
-        vec!["zshrc.d/zshrc_conf", "zshrc"]
-            .iter()
-            .map(|file| system_path.join(file))
-            .collect()
-    }
+    #[test]
+    fn test_unfold_and_fold() {
+        setup_temp(fake_i3_pair_data, |temp_path, under_dir| {
+            let i3_dir = temp_path.join(".config/i3");
+            let all_dir = under_dir.join("i3_all/.config/i3");
 
-    fn fake_i3_data(system_dir: &PathBuf) -> TestData {
-        let dirs_to_make: Vec<PathBuf> =
-            vec![".config", ".under/fake_i3/.config/i3"]
-                .iter()
-                .map(|dir| system_dir.join(dir))
-                .collect();
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            let mut manifest = Manifest::default();
 
-        let files_to_make: Vec<PathBuf> =
-            vec![".under/fake_i3/.config/i3/i3_conf"]
-                .iter()
-                .map(|file| system_dir.join(file))
-                .collect();
+            let mut run = |main: &mut MainApp, action, package: &str| {
+                main.action = action;
+                main.target_dirs = vec![under_dir.join(package)];
 
-        let fake_path = system_dir.join(".under/fake_i3");
+                let ran = match main.action {
+                    Action::Make => make_symlinks(main, &mut manifest),
+                    _ => delete_symlinks(main, &mut manifest),
+                };
 
-        TestData::new(dirs_to_make, files_to_make, fake_path)
+                if let Err(err) = ran {
+                    panic!("cant {} {} {}", main.action, package, err);
+                }
+            };
+
+            run(&mut main, Action::Make, "i3_all");
+            assert_eq!(i3_dir.read_link().unwrap(), all_dir);
+
+            // the second package needs the dir so it gets unfolded
+            run(&mut main, Action::Make, "i3_M");
+            assert!(i3_dir.read_link().is_err(), "dir not unfolded");
+            assert_eq!(
+                i3_dir.join("config").read_link().unwrap(),
+                all_dir.join("config")
+            );
+            assert!(i3_dir.join("monitor").read_link().is_ok());
+
+            // only one package is left in it so it folds back
+            run(&mut main, Action::Delete, "i3_M");
+            assert_eq!(i3_dir.read_link().unwrap(), all_dir);
+
+            run(&mut main, Action::Delete, "i3_all");
+            assert!(i3_dir.symlink_metadata().is_err(), "link left");
+
+            main.no_folding = vec!["i3_*".to_string()];
+            run(&mut main, Action::Make, "i3_all");
+            assert!(i3_dir.is_dir() && i3_dir.read_link().is_err());
+            assert!(i3_dir.join("config").read_link().is_ok());
+        })
     }
 
-    fn hand_made_i3_data(temp_path: &PathBuf) -> Vec<PathBuf> {
-        vec![".config/i3"]
-            .iter()
-            .map(|file| temp_path.join(file))
-            .collect()
+    #[test]
+    fn test_package_roots() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let config_root = temp_path.join("xdg_config");
+            let package_conf = fake_under_zsh.join("@config/zsh/plugins");
+
+            fs::create_dir_all(package_conf.parent().unwrap()).unwrap();
+            fs::write(&package_conf, "").unwrap();
+
+            let mut named = BTreeMap::new();
+            named.insert("config".to_string(), config_root.clone());
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+            main.roots = DestRoots::new(temp_path, &BTreeMap::new(), named);
+
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
+
+            assert_eq!(
+                config_root.join("zsh").read_link().unwrap(),
+                fake_under_zsh.join("@config/zsh")
+            );
+            assert!(temp_path.join("zshrc").read_link().is_ok());
+            assert!(
+                temp_path.join("@config").symlink_metadata().is_err(),
+                "@config was linked"
+            );
+        })
     }
 
-    fn fake_main(tmp: &PathBuf, action: Action) -> MainApp {
-        MainApp::new(Some(tmp.to_owned()), None, action, false, 0, None, None)
-            .unwrap()
+    #[test]
+    fn test_add_paths() {
+        setup_temp(fake_zsh_data, |temp_path, _| {
+            let vim_dir = temp_path.join(".under/vim_all");
+            let vimrc = temp_path.join(".vimrc");
+            let ftplugin = temp_path.join(".vim/ftplugin/rust.vim");
+
+            fs::write(&vimrc, "set nu\n").unwrap();
+            fs::create_dir_all(ftplugin.parent().unwrap()).unwrap();
+            fs::write(&ftplugin, "").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Add);
+            main.target_dirs = vec![vim_dir.clone()];
+            main.to_add = vec![vimrc.clone(), ftplugin.clone()];
+
+            let mut manifest = Manifest::default();
+            if let Err(err) = add_paths(&main, &mut manifest) {
+                panic!("cant add paths {}", err);
+            }
+
+            assert_eq!(vimrc.read_link().unwrap(), vim_dir.join(".vimrc"));
+            assert_eq!(
+                ftplugin.read_link().unwrap(),
+                vim_dir.join(".vim/ftplugin/rust.vim")
+            );
+            assert_eq!(
+                fs::read_to_string(vim_dir.join(".vimrc")).unwrap(),
+                "set nu\n"
+            );
+
+            // a link can not be added and nothing is moved when one fails
+            let zshenv = temp_path.join("zshenv");
+            fs::write(&zshenv, "").unwrap();
+            main.to_add = vec![zshenv.clone(), vimrc.clone()];
+
+            assert!(add_paths(&main, &mut manifest).is_err(), "added a link");
+            assert!(zshenv.symlink_metadata().unwrap().file_type().is_file());
+        })
     }
 
     #[test]
-    fn test_get_symlink_paths_fake_zsh() {
+    fn test_remake_symlinks_zsh() {
         setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
-            let hand_test_zsh: Vec<PathBuf> = hand_made_zsh_data(&temp_path);
+            let temp_path = temp_path.canonicalize().unwrap();
+            let under_dir = temp_path.join(".under");
 
-            let to_sym = match get_symlink_vec(temp_path, fake_under_zsh) {
-                Ok(sym) => sym,
-                Err(err) => {
-                    assert!(false, "failed to get_symlink_paths {}", err);
-                    return; // lol
-                }
-            };
+            // a stale link from a package that is gone
+            let stale = temp_path.join("old_rc");
+            unix::fs::symlink(under_dir.join("fake_old/old_rc"), &stale)
+                .unwrap();
 
-            let flat_syms: Vec<PathBuf> = to_sym
-                .iter()
-                .flat_map(|sym| {
-                    vec![sym.upper_file.to_owned(), sym.target_file.to_owned()]
-                })
-                .collect();
+            // an owned link pointing at the wrong package
+            let moved = temp_path.join("zshrc.d/zshrc_conf");
+            unix::fs::symlink(under_dir.join("fake_old/zshrc_conf"), &moved)
+                .unwrap();
 
-            for hand in hand_test_zsh {
-                assert!(
-                    flat_syms.contains(&hand),
-                    "hand made sym {:?} not found",
-                    hand
+            // far from any package and not in the manifest so not walked to
+            let far = temp_path.join("projects/deep/old_rc");
+            fs::create_dir_all(far.parent().unwrap()).unwrap();
+            unix::fs::symlink(under_dir.join("fake_old/old_rc"), &far).unwrap();
+
+            let mut main = MainApp::fake(&temp_path, Action::Remake);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+
+            if let Err(err) = remake_symlinks(&main, &mut Manifest::default()) {
+                panic!("cant remake symlinks {}", err);
+            }
+
+            assert!(stale.symlink_metadata().is_err(), "stale link left");
+            assert!(far.symlink_metadata().is_ok(), "walked all of upper");
+
+            for hand in hand_made_zsh_data(&temp_path) {
+                let relative = hand.strip_prefix(&temp_path).unwrap();
+
+                assert_eq!(
+                    hand.canonicalize().unwrap(),
+                    fake_under_zsh.join(relative),
+                    "not pointing to right place"
                 );
             }
+
+            assert!(
+                !temp_path.join("zshrc.d/.zshrc_conf.manage_tmp").exists(),
+                "tmp link left behind"
+            );
         })
     }
 
     #[test]
-    fn test_get_symlink_paths_fake_i3() {
-        setup_temp(fake_i3_data, |temp_path, fake_under_i3| {
-            let hand_test_links: Vec<PathBuf> = hand_made_i3_data(temp_path);
+    fn test_delete_symlinks_from_manifest() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let temp_path = temp_path.canonicalize().unwrap();
 
-            let to_sym = match get_symlink_vec(temp_path, fake_under_i3) {
-                Ok(sym) => sym,
-                Err(err) => {
-                    assert!(false, "failed to get_symlink_paths shit {}", err);
-                    return; // lol
-                }
-            };
+            let mut main = MainApp::fake(&temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
 
-            let flat_syms: Vec<PathBuf> = to_sym
-                .iter()
-                .flat_map(|sym| {
-                    vec![sym.upper_file.to_owned(), sym.target_file.to_owned()]
-                })
-                .collect();
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
 
-            for hand in &hand_test_links {
+            let zshrc = temp_path.join("zshrc");
+            assert!(manifest.contains(&zshrc), "link not recorded");
+
+            // the file is gone from under_dir so only the manifest knows
+            fs::remove_file(fake_under_zsh.join("zshrc")).unwrap();
+
+            main.action = Action::Delete;
+            if let Err(err) = delete_symlinks(&main, &mut manifest) {
+                panic!("cant delete symlinks {}", err);
+            }
+
+            for hand in hand_made_zsh_data(&temp_path) {
                 assert!(
-                    flat_syms.contains(&hand),
-                    "hand made sym {:?} not found",
+                    hand.symlink_metadata().is_err(),
+                    "link left {:?}",
                     hand
                 );
             }
+
+            assert!(manifest.links.is_empty(), "manifest not emptied");
         })
     }
 
     #[test]
-    fn test_make_sym_zsh() {
-        setup_temp(fake_zsh_data, |temp_path, _| {
-            let syms = vec![
+    fn test_delete_symlinks_two_repos() {
+        let temp_path = tempdir().unwrap().into_path().canonicalize().unwrap();
+
+        // the same package name in both repos
+        let dots_zsh = temp_path.join(".dots/zsh_all");
+        let work_zsh = temp_path.join(".work/zsh_all");
+        fs::create_dir_all(&dots_zsh).unwrap();
+        fs::create_dir_all(&work_zsh).unwrap();
+        fs::write(dots_zsh.join("zshrc"), "").unwrap();
+        fs::write(work_zsh.join("work_env"), "").unwrap();
+
+        let mut dots = MainApp::fake(&temp_path, Action::Make);
+        dots.under_dir = temp_path.join(".dots");
+        dots.target_dirs = vec![dots_zsh];
+
+        let mut work = MainApp::fake(&temp_path, Action::Make);
+        work.under_dir = temp_path.join(".work");
+        work.target_dirs = vec![work_zsh];
+
+        let mut manifest = Manifest::default();
+        for main in &[&dots, &work] {
+            if let Err(err) = make_symlinks(main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
+        }
+
+        let zshrc = temp_path.join("zshrc");
+        let work_env = temp_path.join("work_env");
+
+        work.action = Action::Delete;
+        if let Err(err) = delete_symlinks(&work, &mut manifest) {
+            panic!("cant delete symlinks {}", err);
+        }
+
+        assert!(work_env.symlink_metadata().is_err(), "work link left");
+        assert!(zshrc.symlink_metadata().is_ok(), "deleted the other repo");
+        assert!(manifest.contains(&zshrc), "other repo dropped");
+
+        // with no packages every link of the other repo looks stale
+        work.target_dirs = vec![];
+        if let Err(err) = delete_symlinks(&work, &mut manifest) {
+            panic!("cant delete symlinks {}", err);
+        }
+
+        assert!(zshrc.symlink_metadata().is_ok(), "deleted the other repo");
+    }
+
+    #[test]
+    fn test_run_sym_vec_rolls_back_make() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let real_file = temp_path.join("zshrc");
+            fs::write(&real_file, "real").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.on_conflict = ConflictPolicy::Overwrite;
+
+            // the last link cant be made so everything before it is undone
+            let to_sym = vec![
+                SymLink::new_conflict(
+                    &real_file,
+                    &fake_under_zsh.join("zshrc"),
+                ),
                 SymLink::new(
-                    &temp_path.join("zshrc"),
-                    &temp_path.join(".under/fake_zsh/zshrc"),
+                    &temp_path.join("zshrc.d/zshrc_conf"),
+                    &fake_under_zsh.join("zshrc.d/zshrc_conf"),
                     false,
                 ),
                 SymLink::new(
-                    &temp_path.join("zshrc.d/zshrc_conf"),
-                    &temp_path.join(".under/fake_zsh/zshrc.d/zshrc_conf"),
+                    &temp_path.join("zshrc.d/zshrc_conf/zshrc_conf"),
+                    &fake_under_zsh.join("zshrc.d/zshrc_conf"),
                     false,
                 ),
             ];
 
-            let main = fake_main(temp_path, Action::Make);
+            match run_sym_vec(&to_sym, &main) {
+                Ok(_) => panic!("made link under a link to a file"),
+                Err(err) => assert!(
+                    matches!(
+                        err.downcast_ref::<ManageError>(),
+                        Some(ManageError::RolledBack { message, .. })
+                            if message == "rolled back 3 change[s]"
+                    ),
+                    "{}",
+                    err
+                ),
+            }
+
+            assert_eq!(fs::read_to_string(&real_file).unwrap(), "real");
+            assert!(
+                temp_path
+                    .join("zshrc.d/zshrc_conf")
+                    .symlink_metadata()
+                    .is_err(),
+                "made link left after rollback"
+            );
+            assert!(
+                !temp_path.join(".zshrc.manage_old").exists(),
+                "overwrite stash left after rollback"
+            );
+        })
+    }
+
+    #[test]
+    fn test_run_sym_vec_rolls_back_delete() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let upper_file = temp_path.join("zshrc");
+            let target_file = fake_under_zsh.join("zshrc");
+            unix::fs::symlink(&target_file, &upper_file).unwrap();
+
+            let main = MainApp::fake(temp_path, Action::Delete);
+
+            // the second link is said to exist but is not there
+            let to_sym = vec![
+                SymLink::new(&upper_file, &target_file, true),
+                SymLink::new(
+                    &temp_path.join("zshrc.d/zshrc_conf"),
+                    &fake_under_zsh.join("zshrc.d/zshrc_conf"),
+                    true,
+                ),
+            ];
+
+            assert!(run_sym_vec(&to_sym, &main).is_err(), "deleted missing");
+
+            assert_eq!(
+                upper_file.read_link().unwrap(),
+                target_file,
+                "deleted link not put back"
+            );
+        })
+    }
+
+    #[test]
+    fn test_template_render_and_delete() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let template = fake_under_zsh.join("polybar.tmpl");
+            fs::write(&template, "font = mono:size={{ font_size }}\n").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+            main.template_vars
+                .insert("font_size".to_string(), "11".to_string());
+
+            let rendered = temp_path.join("polybar");
+
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
+
+            assert!(
+                rendered.symlink_metadata().unwrap().file_type().is_file(),
+                "template was linked not rendered"
+            );
+            assert_eq!(
+                fs::read_to_string(&rendered).unwrap(),
+                "font = mono:size=11\n"
+            );
+
+            main.action = Action::Remake;
+            main.template_vars
+                .insert("font_size".to_string(), "14".to_string());
+
+            if let Err(err) = remake_symlinks(&main, &mut manifest) {
+                panic!("cant remake symlinks {}", err);
+            }
+
+            assert_eq!(
+                fs::read_to_string(&rendered).unwrap(),
+                "font = mono:size=14\n"
+            );
+
+            // hand edits are not thrown away
+            fs::write(&rendered, "font = mono:size=20\n").unwrap();
+
+            main.action = Action::Delete;
+            let syms = get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &ConflictPolicy::Abort,
+            )
+            .unwrap();
+
+            assert!(run_sym_vec(&syms, &main).is_err(), "deleted hand edit");
+            assert!(rendered.exists(), "hand edit gone");
+
+            fs::write(&rendered, "font = mono:size=14\n").unwrap();
 
             if let Err(err) = run_sym_vec(&syms, &main) {
-                println!("{}", err);
-                assert!(false, "cant make symlink")
-            };
+                panic!("cant delete rendered file {}", err);
+            }
 
-            for sym in syms {
-                assert!(sym.upper_file.exists(), "didn't make symlink");
+            assert!(!rendered.exists(), "rendered file left");
+        })
+    }
 
-                let up_conz = sym.upper_file.canonicalize().unwrap();
-                let up_str = up_conz.as_os_str();
+    #[test]
+    fn test_secret_decrypt_and_delete() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let main = MainApp::fake(temp_path, Action::Make);
+            let plain = temp_path.join(".keys/netrc");
+
+            fs::create_dir_all(plain.parent().unwrap()).unwrap();
+            fs::write(&plain, "password hunter2\n").unwrap();
+
+            if let Err(err) = encrypt_file(
+                &plain,
+                &fake_under_zsh.join("netrc.enc"),
+                &main.secret_key_file,
+            ) {
+                panic!("cant encrypt secret {}", err);
+            }
 
-                let lo_str = sym.target_file.as_os_str();
+            let decrypted = temp_path.join("netrc");
+            let mut main = main;
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
 
-                assert!(up_str == lo_str, "not pointing to right place");
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant decrypt secret {}", err);
             }
 
-            assert!(true);
+            let meta = decrypted.symlink_metadata().unwrap();
+            assert!(meta.file_type().is_file(), "secret was linked");
+            assert_eq!(meta.permissions().mode() & 0o777, SECRET_MODE);
+            assert_eq!(
+                fs::read_to_string(&decrypted).unwrap(),
+                "password hunter2\n"
+            );
+            assert_eq!(
+                manifest.checksum(&decrypted),
+                Some(file_checksum(&decrypted).unwrap()),
+                "secret not recorded"
+            );
+
+            // the secret and its key are gone so only the manifest knows
+            fs::remove_file(fake_under_zsh.join("netrc.enc")).unwrap();
+            fs::remove_file(&main.secret_key_file).unwrap();
+
+            main.action = Action::Delete;
+            if let Err(err) = delete_symlinks(&main, &mut manifest) {
+                panic!("cant delete decrypted secret {}", err);
+            }
+
+            assert!(!decrypted.exists(), "decrypted secret left");
+            assert!(manifest.links.is_empty(), "manifest not emptied");
         })
     }
 
     #[test]
-    fn test_make_sym_i3() {
-        setup_temp(fake_i3_data, |temp_path, _| {
-            let syms = vec![SymLink::new(
-                &temp_path.join(".config/i3"),
-                &temp_path.join(".under/fake_i3/.config/i3"),
-                false,
-            )];
+    fn test_rendered_over_unmanaged_file() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            fs::write(fake_under_zsh.join("polybar.tmpl"), "font = mono\n")
+                .unwrap();
+
+            let mine = temp_path.join("polybar");
+            fs::write(&mine, "mine\n").unwrap();
+
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+
+            // a hand written netrc is kept the same way
+            let plain = temp_path.join(".keys/netrc");
+            fs::create_dir_all(plain.parent().unwrap()).unwrap();
+            fs::write(&plain, "password hunter2\n").unwrap();
+            encrypt_file(
+                &plain,
+                &fake_under_zsh.join("netrc.enc"),
+                &main.secret_key_file,
+            )
+            .unwrap();
+
+            let netrc = temp_path.join("netrc");
+            fs::write(&netrc, "machine mine\n").unwrap();
+
+            let mut manifest = Manifest::default();
+            match make_symlinks(&main, &mut manifest) {
+                Err(err) => assert!(
+                    matches!(
+                        ManageError::from(err),
+                        ManageError::Conflict { ref path, .. }
+                            if *path == mine || *path == netrc
+                    ),
+                    "not a conflict"
+                ),
+                Ok(_) => panic!("rendered over a file manage did not write"),
+            }
 
-            let main = fake_main(temp_path, Action::Make);
+            assert_eq!(fs::read_to_string(&mine).unwrap(), "mine\n");
+            assert_eq!(fs::read_to_string(&netrc).unwrap(), "machine mine\n");
 
-            if let Err(err) = run_sym_vec(&syms, &main) {
-                println!("{}", err);
-                assert!(false, "cant make symlink")
-            };
+            main.on_conflict = ConflictPolicy::Backup;
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
 
-            for sym in syms {
-                assert!(sym.upper_file.exists(), "didnt make symlink");
+            assert_eq!(fs::read_to_string(&mine).unwrap(), "font = mono\n");
+            assert_eq!(
+                fs::read_to_string(main.backup_dir.join("polybar")).unwrap(),
+                "mine\n"
+            );
+            assert_eq!(
+                fs::read_to_string(&netrc).unwrap(),
+                "password hunter2\n"
+            );
+            assert_eq!(
+                fs::read_to_string(main.backup_dir.join("netrc")).unwrap(),
+                "machine mine\n"
+            );
+
+            // a rendered file edited by hand is not ours any more
+            fs::write(&mine, "edited\n").unwrap();
+            main.on_conflict = ConflictPolicy::Abort;
+
+            assert!(
+                make_symlinks(&main, &mut manifest).is_err(),
+                "rendered over a hand edit"
+            );
+            assert_eq!(fs::read_to_string(&mine).unwrap(), "edited\n");
+        })
+    }
 
-                let up_conz = sym.upper_file.canonicalize().unwrap();
-                let up_str = up_conz.as_os_str();
+    #[test]
+    fn test_copy_drift_and_write_back() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let temp_path = temp_path.canonicalize().unwrap();
+            let under_zshrc = fake_under_zsh.join("zshrc");
+            fs::write(&under_zshrc, "from under\n").unwrap();
+
+            let mut main = MainApp::fake(&temp_path, Action::Make);
+            main.target_dirs = vec![fake_under_zsh.to_owned()];
+            main.copy_rule =
+                CopyRule::new(&main.under_dir, &["fake_zsh/zshrc".to_string()]);
+
+            let mut manifest = Manifest::default();
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant make symlinks {}", err);
+            }
 
-                let lo_str = sym.target_file.as_os_str();
+            let copied = temp_path.join("zshrc");
+            assert!(
+                copied.symlink_metadata().unwrap().file_type().is_file(),
+                "zshrc was linked not copied"
+            );
+            assert!(
+                manifest.checksum(&copied).is_some(),
+                "copy checksum not recorded"
+            );
+
+            // local edits stop the next deploy
+            fs::write(&copied, "edited\n").unwrap();
+            fs::write(&under_zshrc, "new from under\n").unwrap();
+
+            match make_symlinks(&main, &mut manifest) {
+                Err(err) => {
+                    assert_eq!(ManageError::from(err).exit_code(), 5)
+                }
+                Ok(_) => panic!("replaced local edits"),
+            }
+            assert_eq!(fs::read_to_string(&copied).unwrap(), "edited\n");
 
-                assert!(up_str == lo_str, "not pointing to right place");
+            main.write_back = true;
+            if let Err(err) = make_symlinks(&main, &mut manifest) {
+                panic!("cant write back {}", err);
             }
 
-            assert!(true);
+            assert_eq!(fs::read_to_string(&under_zshrc).unwrap(), "edited\n");
+            assert_eq!(
+                manifest.checksum(&copied),
+                Some(file_checksum(&copied).unwrap())
+            );
+
+            main.action = Action::Delete;
+            if let Err(err) = delete_symlinks(&main, &mut manifest) {
+                panic!("cant delete copy {}", err);
+            }
+
+            assert!(!copied.exists(), "copy left after delete");
+            assert!(under_zshrc.exists(), "under file deleted");
         })
     }
 
     #[test]
-    fn test_make_delete_symlink_zsh() {
-        setup_temp(fake_zsh_data, |temp_path, _| {
-            let syms = vec![
-                SymLink::new(
-                    &temp_path.join("zshrc.d/zshrc_conf"),
-                    &temp_path.join(".under/fake_zsh/zshrc.d/zshrc_conf"),
-                    true,
+    fn test_walk_missing_target_dir() {
+        let tmp = tempdir().unwrap().into_path();
+        let missing = tmp.join(".under/gone_all");
+
+        match get_symlink_vec(&tmp, &missing, &ConflictPolicy::Abort) {
+            Ok(_) => panic!("walked a missing dir"),
+            Err(err) => assert!(
+                matches!(
+                    err.downcast_ref::<ManageError>(),
+                    Some(ManageError::Io { path: Some(path), .. })
+                        if *path == missing
                 ),
-                SymLink::new(
-                    &temp_path.join("zshrc"),
-                    &temp_path.join(".under/fake_zsh/zshrc"),
-                    true,
+                "{}",
+                err
+            ),
+        }
+    }
+
+    #[test]
+    fn test_link_action() {
+        let tmp = tempdir().unwrap().into_path();
+        let target = tmp.join(".under/zsh_all/zshrc.tmpl");
+        let template = SymLink::new_rendered(
+            &tmp.join("zshrc"),
+            &target,
+            false,
+            LinkKind::Template,
+        );
+        let conflict = SymLink::new_conflict(&tmp.join("zshrc"), &target);
+
+        let mut main = MainApp::fake(&tmp, Action::Make);
+        main.on_conflict = ConflictPolicy::Backup;
+
+        assert_eq!(link_action(&template, &main), "render");
+        assert_eq!(link_action(&conflict, &main), "backup then make");
+        assert_eq!(package_name(&target, &main.under_dir), "zsh_all");
+
+        main.action = Action::Delete;
+        assert_eq!(link_action(&conflict, &main), "delete");
+    }
+
+    #[test]
+    fn test_conflict_policy_abort_and_skip() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let real_file = temp_path.join("zshrc");
+            fs::write(&real_file, "real").unwrap();
+
+            let aborted = get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &ConflictPolicy::Abort,
+            );
+            match aborted {
+                Err(err) => assert!(
+                    matches!(
+                        err.downcast_ref::<ManageError>(),
+                        Some(ManageError::Conflict { path, .. })
+                            if *path == real_file
+                    ),
+                    "{}",
+                    err
                 ),
-            ];
+                Ok(_) => panic!("didn't abort on existing file"),
+            }
 
-            let main = fake_main(temp_path, Action::Delete);
+            let to_sym = match get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &ConflictPolicy::Skip,
+            ) {
+                Ok(sym) => sym,
+                Err(err) => panic!("failed to get_symlink_vec {}", err),
+            };
 
-            for sym in &syms {
-                assert!(!sym.upper_file.exists(), "file exists?");
+            assert!(
+                !to_sym.iter().any(|sym| sym.upper_file == real_file),
+                "didn't skip existing file"
+            );
+        })
+    }
 
-                unix::fs::symlink(&sym.target_file, &sym.upper_file).unwrap();
+    #[test]
+    fn test_conflict_policy_backup() {
+        setup_temp(fake_zsh_data, |temp_path, fake_under_zsh| {
+            let real_file = temp_path.join("zshrc");
+            fs::write(&real_file, "real").unwrap();
 
-                assert!(sym.upper_file.exists(), "didn't make sym");
-            }
+            let mut main = MainApp::fake(temp_path, Action::Make);
+            main.on_conflict = ConflictPolicy::Backup;
 
-            if let Err(err) = run_sym_vec(&syms, &main) {
-                println!("{}", err);
-                assert!(false, "cant delete symlink")
+            let to_sym = match get_symlink_vec(
+                temp_path,
+                fake_under_zsh,
+                &main.on_conflict,
+            ) {
+                Ok(sym) => sym,
+                Err(err) => panic!("failed to get_symlink_vec {}", err),
             };
 
-            for sym in syms {
-                assert!(!sym.upper_file.exists(), "didn't remove symlink");
+            assert!(
+                to_sym.iter().any(|sym| sym.conflict),
+                "didn't find the conflict"
+            );
+
+            if let Err(err) = run_sym_vec(&to_sym, &main) {
+                panic!("cant make symlinks {}", err);
             }
 
-            assert!(true);
+            assert_eq!(
+                real_file.canonicalize().unwrap(),
+                fake_under_zsh.join("zshrc"),
+                "not pointing to right place"
+            );
+
+            assert_eq!(
+                fs::read_to_string(main.backup_dir.join("zshrc")).unwrap(),
+                "real"
+            );
         })
     }
 }
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::my_utils::get_linux_hostname;
+use crate::user_config::Target;
+
+pub type TemplateVars = BTreeMap<String, String>;
+
+// files ending in .tmpl are rendered in to upper_dir instead of linked
+pub const TEMPLATE_EXT: &str = "tmpl";
+
+pub fn is_template(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new(TEMPLATE_EXT)) && path.is_file()
+}
+
+// the name the rendered file gets in upper_dir, zshrc.tmpl -> zshrc
+pub fn rendered_name(path: &Path) -> Option<&OsStr> {
+    path.file_stem()
+}
+
+// facts about this machine, then the vars of the target in use
+pub fn make_template_vars(target: Option<&Target>) -> TemplateVars {
+    let mut vars = TemplateVars::new();
+
+    if let Some(hostname) = get_linux_hostname() {
+        vars.insert("hostname".to_string(), hostname);
+    }
+
+    for (name, env_var) in &[("user", "USER"), ("home", "HOME")] {
+        if let Ok(value) = env::var(env_var) {
+            vars.insert(name.to_string(), value);
+        }
+    }
+
+    vars.insert("os".to_string(), env::consts::OS.to_string());
+
+    if let Some(target) = target {
+        vars.insert("target".to_string(), target.name.clone());
+
+        for (name, value) in &target.vars {
+            vars.insert(name.clone(), value.clone());
+        }
+    }
+
+    vars
+}
+
+// replace every {{ name }} with its var
+pub fn render_str(
+    template: &str,
+    vars: &TemplateVars,
+) -> Result<String, Box<dyn Error>> {
+    let mut to_ret = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find("{{") {
+        let line = template[..template.len() - rest.len() + open]
+            .matches('\n')
+            .count()
+            + 1;
+
+        to_ret.push_str(&rest[..open]);
+
+        let after_open = &rest[open + 2..];
+        let close = after_open
+            .find("}}")
+            .ok_or_else(|| format!("unclosed {{{{ on line {}", line))?;
+
+        let name = after_open[..close].trim();
+        let value = vars.get(name).ok_or_else(|| {
+            format!("unknown template var {} on line {}", name, line)
+        })?;
+
+        to_ret.push_str(value);
+        rest = &after_open[close + 2..];
+    }
+
+    to_ret.push_str(rest);
+
+    Ok(to_ret)
+}
+
+pub fn render_template(
+    template_file: &Path,
+    vars: &TemplateVars,
+) -> Result<String, Box<dyn Error>> {
+    let template = fs::read_to_string(template_file)
+        .map_err(|err| format!("cant read {:?} {}", template_file, err))?;
+
+    render_str(&template, vars)
+        .map_err(|err| Box::from(format!("{:?}: {}", template_file, err)))
+}
+
+// the current rendered file if there is one
+pub fn read_rendered(upper_file: &Path) -> Option<String> {
+    if upper_file.symlink_metadata().ok()?.file_type().is_file() {
+        fs::read_to_string(upper_file).ok()
+    } else {
+        None
+    }
+}
+
+// a line diff of old to new, lines only in old start with - and lines only in
+// new start with +
+pub fn line_diff(old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // longest common subsequence lengths from the end of both
+    let mut lcs = vec![vec![0; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            lcs[i][j] = if old_lines[i] == new_lines[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut to_ret: Vec<String> = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_lines.len() || j < new_lines.len() {
+        if i < old_lines.len()
+            && j < new_lines.len()
+            && old_lines[i] == new_lines[j]
+        {
+            to_ret.push(format!("  {}", old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if i < old_lines.len()
+            && (j == new_lines.len() || lcs[i + 1][j] >= lcs[i][j + 1])
+        {
+            to_ret.push(format!("- {}", old_lines[i]));
+            i += 1;
+        } else {
+            to_ret.push(format!("+ {}", new_lines[j]));
+            j += 1;
+        }
+    }
+
+    to_ret.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fake_vars() -> TemplateVars {
+        let mut vars = TemplateVars::new();
+        vars.insert("monitor".to_string(), "DP-1".to_string());
+        vars.insert("font_size".to_string(), "11".to_string());
+        vars
+    }
+
+    #[test]
+    fn test_render_str() {
+        let rendered = render_str(
+            "monitor = {{monitor}}\nfont = mono:size={{ font_size }}\n",
+            &fake_vars(),
+        );
+
+        match rendered {
+            Ok(rendered) => {
+                assert_eq!(rendered, "monitor = DP-1\nfont = mono:size=11\n")
+            }
+            Err(err) => panic!("cant render {}", err),
+        }
+
+        match render_str("a\nb {{ nope }}", &fake_vars()) {
+            Ok(_) => panic!("rendered unknown var"),
+            Err(err) => assert_eq!(
+                format!("{}", err),
+                "unknown template var nope on line 2"
+            ),
+        }
+
+        assert!(render_str("{{ monitor", &fake_vars()).is_err());
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let diff = line_diff(
+            "bar\nmonitor = DP-1\nend\n",
+            "bar\nmonitor = HDMI\nend\n",
+        );
+
+        assert_eq!(diff, "  bar\n- monitor = DP-1\n+ monitor = HDMI\n  end");
+
+        assert_eq!(line_diff("", "new\n"), "+ new");
+    }
+}
@@ -1,87 +1,393 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::error::Error;
+use std::fmt;
+use std::fs;
 use std::path::PathBuf;
 
-use toml::Value;
+use regex::Regex;
+use serde::Deserialize;
+use toml::{value::Table, Value};
 
 use crate::auto_filters::FilterType;
-use crate::my_utils::{make_then_check_path, open_file_or_panic};
+use crate::error::Error as ManageError;
+use crate::glob_utils::check_glob;
+use crate::hook_utils::PackageHooks;
+use crate::ignore_utils::check_ignore;
+use crate::my_utils::{get_xdg_config_path, str_to_path};
+use crate::root_utils::ROOT_NAMES;
+use crate::symbolic_link::{ConflictPolicy, LinkStyle};
 
 #[derive(Debug)]
 pub struct Target {
     pub name: String,
     pub file_patterns: Vec<String>,
+    pub file_regex: Vec<Regex>,
+    // used by templates
+    pub vars: BTreeMap<String, String>,
 }
 
 pub struct Conf {
     pub under_dir: String,
     pub upper_dir: String,
     pub filter_type: FilterType,
+    pub keyfile: PathBuf,
     pub to_filter: Vec<Target>,
     pub git_url: Option<String>,
+    pub on_conflict: ConflictPolicy,
+    pub link_style: LinkStyle,
+    pub copy: Vec<String>,
+    pub ignore: Vec<String>,
+    pub no_folding: Vec<String>,
+    pub secret_key: Option<PathBuf>,
+    pub destinations: BTreeMap<String, String>,
+    pub hooks: PackageHooks,
+}
+
+// the config file as it is written, the defaults all live here
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BaseVars {
+    // defaults to HOME
+    pub base: Option<String>,
+    // defaults to base
+    pub upper_dir: Option<String>,
+    // no default
+    pub under_dir: Option<String>,
+    pub git_url: Option<String>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    #[serde(default)]
+    pub link_style: LinkStyle,
+}
+
+fn default_keyfile() -> String {
+    ".manage_keyfile".to_string()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRules {
+    #[serde(default)]
+    pub filter_type: FilterType,
+    #[serde(default = "default_keyfile")]
+    pub keyfile: String,
+    // not used yet
+    pub selection_type: Option<String>,
+}
+
+impl Default for FilterRules {
+    fn default() -> Self {
+        FilterRules {
+            filter_type: FilterType::default(),
+            keyfile: default_keyfile(),
+            selection_type: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Deploy {
+    // paths in under_dir to copy instead of link
+    #[serde(default)]
+    pub copy: Vec<String>,
+    // patterns ignored in every package
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    // packages that always get real dirs instead of dir links
+    #[serde(default)]
+    pub no_folding: Vec<String>,
+    // the key for .enc files, defaults to the manage config dir
+    pub secret_key: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConf {
+    #[serde(default)]
+    pub file_patterns: Vec<String>,
+    #[serde(default)]
+    pub file_regex: Vec<String>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConfFile {
+    pub base_vars: Option<BaseVars>,
+    #[serde(default)]
+    pub filter_rules: FilterRules,
+    #[serde(default)]
+    pub deploy: Deploy,
+    #[serde(default)]
+    pub targets: BTreeMap<String, TargetConf>,
+    #[serde(default)]
+    pub hooks: PackageHooks,
+    // package name to where it is linked to instead of upper_dir
+    #[serde(default)]
+    pub destinations: BTreeMap<String, String>,
+    // anything else has to be an old style top level target table
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+// base_vars.base, the dir paths in the config start from
+pub fn base_dir(base: Option<&str>) -> Result<String, String> {
+    match base {
+        Some(base_dir) => Ok(base_dir.to_string()),
+        None => env::var("HOME").map_err(|err| format!("no HOME var {}", err)),
+    }
+}
+
+// ~/ and relative paths both start from base
+pub fn from_base(base_dir: &str, path: &str) -> PathBuf {
+    str_to_path(&[base_dir, path.trim_start_matches("~/")])
+}
+
+// the top level tables ConfFile has a field for, any other key ends up in
+// other
+pub fn is_section(key: &str) -> bool {
+    let mut table = Table::new();
+    table.insert(key.to_string(), Value::Table(Table::new()));
+
+    match Value::Table(table).try_into::<ConfFile>() {
+        Ok(conf_file) => conf_file.other.is_empty(),
+        Err(_) => true,
+    }
+}
+
+// an old style top level target picks packages, a table that does not is
+// more likely a misspelled one like [targest]
+pub fn check_old_target(key: &str, value: &Value) -> Result<(), String> {
+    if !value.is_table() {
+        Err(format!("unknown key {}", key))
+    } else if value.get("file_patterns").is_none()
+        && value.get("file_regex").is_none()
+    {
+        Err(format!(
+            "unknown table {}, a top level target needs file_patterns or \
+             file_regex",
+            key
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// a value that reads in to the config structs but cant be used, key is
+// where it was given and item the array item if it is one
+#[derive(Debug)]
+pub struct Invalid {
+    pub key: String,
+    pub item: Option<String>,
+    pub message: String,
+}
+
+// each pattern that check fails on, the message reads "bad KEY WHAT: err"
+fn invalid_patterns<C, T, E>(
+    key: &str,
+    what: &str,
+    patterns: &[String],
+    check: C,
+) -> Vec<Invalid>
+where
+    C: Fn(&str) -> Result<T, E>,
+    E: fmt::Display,
+{
+    patterns
+        .iter()
+        .filter_map(|pattern| {
+            check(pattern).err().map(|err| Invalid {
+                key: key.to_string(),
+                item: Some(pattern.clone()),
+                message: format!("bad {} {}: {}", key, what, err),
+            })
+        })
+        .collect()
+}
+
+// Conf::new stops at the first one, check-config reports them all
+fn first_invalid(found: Vec<Invalid>) -> Result<(), Box<dyn Error>> {
+    match found.into_iter().next() {
+        Some(invalid) => Err(Box::from(invalid.message)),
+        None => Ok(()),
+    }
+}
+
+impl Deploy {
+    pub fn invalid(&self) -> Vec<Invalid> {
+        let what = "pattern";
+
+        let mut to_ret = invalid_patterns("copy", what, &self.copy, check_glob);
+        to_ret.extend(invalid_patterns(
+            "ignore",
+            what,
+            &self.ignore,
+            check_ignore,
+        ));
+        to_ret.extend(invalid_patterns(
+            "no_folding",
+            what,
+            &self.no_folding,
+            check_glob,
+        ));
+
+        to_ret
+    }
+}
+
+impl TargetConf {
+    pub fn invalid(&self, name: &str) -> Vec<Invalid> {
+        let what = format!("in {}", name);
+
+        // a leading ! takes names back out and is not part of the glob
+        let mut to_ret = invalid_patterns(
+            "file_patterns",
+            &what,
+            &self.file_patterns,
+            |pattern: &str| check_glob(pattern.trim_start_matches('!')),
+        );
+        to_ret.extend(invalid_patterns(
+            "file_regex",
+            &what,
+            &self.file_regex,
+            Regex::new,
+        ));
+
+        to_ret
+    }
+}
+
+// destinations starting with a root that does not exist
+pub fn invalid_destinations(
+    destinations: &BTreeMap<String, String>,
+) -> Vec<Invalid> {
+    let names: Vec<String> = ROOT_NAMES
+        .iter()
+        .map(|(root, _, _)| format!("@{}", root))
+        .collect();
+
+    destinations
+        .iter()
+        .filter_map(|(package, dest)| {
+            let name = dest.strip_prefix('@')?.split('/').next()?;
+
+            if ROOT_NAMES.iter().any(|(root, _, _)| *root == name) {
+                return None;
+            }
+
+            Some(Invalid {
+                key: package.clone(),
+                item: None,
+                message: format!(
+                    "unknown root @{} for {}, expected one of {}",
+                    name,
+                    package,
+                    names.join(", ")
+                ),
+            })
+        })
+        .collect()
+}
+
+impl ConfFile {
+    // [targets.NAME] tables then the old top level ones
+    fn all_targets(self) -> Result<Vec<(String, TargetConf)>, Box<dyn Error>> {
+        let mut to_ret: Vec<(String, TargetConf)> =
+            self.targets.into_iter().collect();
+
+        for (key, value) in self.other {
+            check_old_target(&key, &value)?;
+
+            if to_ret.iter().any(|(name, _)| *name == key) {
+                return Err(Box::from(format!("target {} given twice", key)));
+            }
+
+            let target: TargetConf = value
+                .try_into()
+                .map_err(|err| format!("bad target {}: {}", key, err))?;
+
+            to_ret.push((key, target));
+        }
+
+        Ok(to_ret)
+    }
+}
+
+impl Target {
+    pub fn new(
+        name: String,
+        target: TargetConf,
+    ) -> Result<Self, Box<dyn Error>> {
+        first_invalid(target.invalid(&name))?;
+
+        let file_regex: Vec<Regex> = target
+            .file_regex
+            .iter()
+            .map(|reg| Regex::new(reg))
+            .collect::<Result<Vec<Regex>, regex::Error>>()?;
+
+        Ok(Target {
+            name,
+            file_patterns: target.file_patterns,
+            file_regex,
+            vars: target.vars,
+        })
+    }
 }
 
 impl Conf {
-    fn new(toml_val: Value) -> Result<Self, Box<dyn Error>> {
-        let base_vars: &Value = match toml_val.get("base_vars") {
+    fn new(conf_file: ConfFile) -> Result<Self, Box<dyn Error>> {
+        let base_vars: &BaseVars = match &conf_file.base_vars {
             Some(base_vars) => base_vars,
             None => return Err(Box::from("need base vars")),
         };
 
-        let base_dir = if let Some(b_str) = base_vars.get("base") {
-            b_str.clone().to_string()
-        } else {
-            env::var("HOME").map_err(|err| format!("no HOME var {}", err))?
-        };
+        let base_dir = base_dir(base_vars.base.as_deref())?;
 
-        let upper_dir = match base_vars.get("upper_dir") {
-            Some(upper_dir) => upper_dir.to_string(),
+        let upper_dir = match &base_vars.upper_dir {
+            Some(upper_dir) => upper_dir.clone(),
             None => base_dir.clone(),
         };
 
-        let under_dir = match base_vars.get("under_dir") {
-            Some(under_dir) => under_dir.clone().to_string(),
+        let under_dir = match &base_vars.under_dir {
+            Some(under_dir) => under_dir.clone(),
             None => return Err(Box::from("need under_dir")),
         };
 
-        let git_url = match base_vars.get("git_url") {
-            Some(git_url) => Some(git_url.clone().to_string()),
-            None => None,
-        };
+        let git_url = base_vars.git_url.clone();
+        let on_conflict = base_vars.on_conflict.clone();
+        let link_style = base_vars.link_style.clone();
 
-        let filter_rules: Option<&Value> = toml_val.get("filter_rules");
+        let filter_rules = &conf_file.filter_rules;
+        let filter_type = filter_rules.filter_type.clone();
 
-        let filter_type = if let Some(f_rules) = filter_rules {
-            match f_rules.get("filter_type") {
-                Some(maybe_str) => match maybe_str.as_str() {
-                    Some(val) if val == "hostname" => FilterType::Hostname,
-                    _ => FilterType::Keyfile,
-                },
-                None => FilterType::Keyfile,
-            }
-        } else {
-            FilterType::Keyfile
-        };
+        let keyfile: PathBuf = from_base(&base_dir, &filter_rules.keyfile);
 
-        let mut to_filter: Vec<Target> = vec![];
+        first_invalid(conf_file.deploy.invalid())?;
 
-        for (key, value) in toml_val.as_table().unwrap() {
-            if let Some(target) = value.get("file_patterns") {
-                let name = key.to_owned();
-                let file_patterns: Vec<String> = target
-                    .as_array()
-                    .map(|vec| {
-                        vec.iter().map(|ele| ele.as_str().unwrap().to_owned())
-                    })
-                    .ok_or("can't get array from patterns")?
-                    .collect();
+        let copy: Vec<String> = conf_file.deploy.copy.clone();
+        let ignore: Vec<String> = conf_file.deploy.ignore.clone();
+        let no_folding: Vec<String> = conf_file.deploy.no_folding.clone();
 
-                to_filter.push(Target {
-                    name,
-                    file_patterns,
-                });
-            }
-        }
+        let secret_key: Option<PathBuf> = conf_file
+            .deploy
+            .secret_key
+            .as_ref()
+            .map(|secret_key| from_base(&base_dir, secret_key));
+
+        let destinations = conf_file.destinations.clone();
+        first_invalid(invalid_destinations(&destinations))?;
+
+        let hooks = conf_file.hooks.clone();
+
+        let to_filter: Vec<Target> = conf_file
+            .all_targets()?
+            .into_iter()
+            .map(|(name, target)| Target::new(name, target))
+            .collect::<Result<Vec<Target>, Box<dyn Error>>>()?;
 
         if to_filter.is_empty() {
             return Err(Box::from("no filters given"));
@@ -89,25 +395,51 @@ impl Conf {
 
         Ok(Conf {
             filter_type,
+            keyfile,
             upper_dir,
             under_dir,
             to_filter,
             git_url,
+            on_conflict,
+            link_style,
+            copy,
+            ignore,
+            no_folding,
+            secret_key,
+            destinations,
+            hooks,
         })
     }
 }
 
-pub fn make_config(config_path: &PathBuf) -> Result<Conf, Box<dyn Error>> {
-    let toml_string: String = open_file_or_panic(config_path);
-    let toml: Value = toml_string.parse::<Value>()?;
-    Conf::new(toml)
+pub fn make_config(config_path: &PathBuf) -> Result<Conf, ManageError> {
+    let config_error = |message: String| ManageError::Config {
+        path: Some(config_path.clone()),
+        message,
+    };
+
+    let toml_string: String = fs::read_to_string(config_path)
+        .map_err(|err| config_error(format!("cant read config {}", err)))?;
+    let conf_file: ConfFile = toml::from_str(&toml_string)
+        .map_err(|err| config_error(err.to_string()))?;
+
+    Conf::new(conf_file).map_err(|err| config_error(err.to_string()))
 }
 
-pub fn get_xdg_user_config_path() -> Result<PathBuf, Box<dyn Error>> {
-    match env::var("XDG_CONFIG_HOME") {
-        Ok(var) => make_then_check_path(&[&var, "manage"])
-            .ok_or_else(|| Box::from("config dose not exist")),
-        Err(err) => Err(Box::from(format!("no XDG_CONFIG_HOME {}", err))),
+pub fn get_xdg_user_config_path() -> Result<PathBuf, ManageError> {
+    let config_path =
+        get_xdg_config_path().map_err(|message| ManageError::Config {
+            path: None,
+            message,
+        })?;
+
+    if config_path.exists() {
+        Ok(config_path)
+    } else {
+        Err(ManageError::Config {
+            path: Some(config_path),
+            message: "no config, run manage init to make one".to_string(),
+        })
     }
 }
 
@@ -115,9 +447,10 @@ pub fn get_xdg_user_config_path() -> Result<PathBuf, Box<dyn Error>> {
 mod test {
     // thanks https://medium.com/@ericdreichert/
     //       test-setup-and-teardown-in-rust-without-a-framework-ba32d97aa5ab
-    use std::borrow::Borrow;
     use std::fs;
     use std::panic;
+    use std::path::Path;
+
     use tempfile::tempdir;
 
     use super::*;
@@ -144,7 +477,7 @@ mod test {
 
     fn setup_temp<Y, T>(fake_data: Y, test: T)
     where
-        Y: FnOnce(&PathBuf) -> TestData,
+        Y: FnOnce(&Path) -> TestData,
         T: FnOnce(&PathBuf, &PathBuf),
     {
         let test_dir = tempdir().unwrap();
@@ -159,30 +492,28 @@ mod test {
 
         for dir in dirs_to_make {
             if let Err(err) = fs::create_dir_all(&dir) {
-                assert!(false, "dir {:?} make error {}", dir, err);
+                panic!("dir {:?} make error {}", dir, err);
             }
         }
 
         for file in files_to_make {
             if let Err(err) = fs::File::create(&file) {
-                assert!(false, "file {:?} make error {}", file, err);
+                panic!("file {:?} make error {}", file, err);
             }
         }
 
         assert!(fake_path.exists(), "tempdir fails");
 
         test(&temp_path, &fake_path);
-
-        assert!(true, "idk how this would fail");
     }
 
-    fn fake_config_data(test_dir: &PathBuf) -> TestData {
-        let dir_vec: Vec<PathBuf> = vec![".config/manage"]
+    fn fake_config_data(test_dir: &Path) -> TestData {
+        let dir_vec: Vec<PathBuf> = [".config/manage"]
             .iter()
             .map(|val| test_dir.join(val))
             .collect();
 
-        let file_vec: Vec<PathBuf> = vec![".config/manage/config"]
+        let file_vec: Vec<PathBuf> = [".config/manage/config"]
             .iter()
             .map(|val| test_dir.join(val))
             .collect();
@@ -212,16 +543,14 @@ mod test {
                 "#;
 
             if let Err(err) = fs::write(fake_config_path, fake_config) {
-                assert!(false, "{}", err);
+                panic!("{}", err);
             }
 
             if let Ok(conf) = make_config(fake_config_path) {
-                assert!(true, "made config");
                 assert!(!conf.under_dir.is_empty(), "didn't get under dir");
                 assert!(!conf.to_filter.is_empty(), "didn't get to_filter");
             } else {
-                assert!(false, "broken config");
-                panic!("fuck");
+                panic!("broken config");
             }
         })
     }
@@ -243,18 +572,16 @@ mod test {
                 "#;
 
             if let Err(err) = fs::write(fake_config_path, fake_config) {
-                assert!(false, "{}", err);
+                panic!("{}", err);
             }
 
-            if let Err(err) = make_config(fake_config_path) {
-                let error: &Error = err.borrow();
-                if format!("{}", error) == "need under_dir" {
-                    assert!(true, "{}", err);
-                } else {
-                    assert!(false, "{}", err);
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config"),
+                Err(ManageError::Config { path, message }) => {
+                    assert_eq!(path.as_ref(), Some(fake_config_path));
+                    assert_eq!(message, "need under_dir");
                 }
-            } else {
-                assert!(false, "made config");
+                Err(err) => panic!("not a config error {}", err),
             }
         })
     }
@@ -276,18 +603,207 @@ mod test {
                 "#;
 
             if let Err(err) = fs::write(fake_config_path, fake_config) {
-                assert!(false, "{}", err);
+                panic!("{}", err);
             }
 
-            if let Err(err) = make_config(fake_config_path) {
-                let error: &Error = err.borrow();
-                if format!("{}", error) == "no filters given" {
-                    assert!(true, "{}", err);
-                } else {
-                    assert!(false, "{}", err);
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config"),
+                Err(ManageError::Config { message, .. }) => {
+                    assert_eq!(message, "no filters given")
                 }
-            } else {
-                assert!(false, "made config");
+                Err(err) => panic!("not a config error {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_config_file_regex() {
+        setup_temp(fake_config_data, |_, fake_config_path| {
+            let fake_config = r#"
+                [base_vars]
+                under_dir = '.dots'
+
+                [Monolith]
+                file_patterns = ['*_all']
+                file_regex = ['^(zsh|i3)_(laptop|desk)$']
+
+                [Odimm]
+                file_regex = ['^polybar_O$']
+                "#;
+
+            if let Err(err) = fs::write(fake_config_path, fake_config) {
+                panic!("{}", err);
+            }
+
+            match make_config(fake_config_path) {
+                Ok(conf) => {
+                    assert_eq!(conf.to_filter.len(), 2);
+
+                    for target in conf.to_filter {
+                        assert_eq!(target.file_regex.len(), 1);
+                    }
+                }
+                Err(err) => panic!("broken config {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_broken_config_file_regex() {
+        setup_temp(fake_config_data, |_, fake_config_path| {
+            let fake_config = r#"
+                [base_vars]
+                under_dir = '.dots'
+
+                [Monolith]
+                file_regex = ['^(zsh|i3_laptop$']
+                "#;
+
+            if let Err(err) = fs::write(fake_config_path, fake_config) {
+                panic!("{}", err);
+            }
+
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config"),
+                Err(ManageError::Config { message, .. }) => assert!(
+                    message.starts_with("bad file_regex in Monolith"),
+                    "{}",
+                    message
+                ),
+                Err(err) => panic!("not a config error {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_config_targets_table() {
+        setup_temp(fake_config_data, |_, fake_config_path| {
+            let fake_config = r#"
+                [base_vars]
+                base = '/home/fake'
+                under_dir = '.dots'
+
+                [filter_rules]
+                keyfile = '~/.config/manage_key'
+
+                [targets.Monolith]
+                file_patterns = ['*_all', '*_M']
+
+                # the old top level tables still work
+                [Odimm]
+                file_patterns = ['*_all', '*_O']
+                "#;
+
+            if let Err(err) = fs::write(fake_config_path, fake_config) {
+                panic!("{}", err);
+            }
+
+            match make_config(fake_config_path) {
+                Ok(conf) => {
+                    let names: Vec<&str> = conf
+                        .to_filter
+                        .iter()
+                        .map(|target| target.name.as_str())
+                        .collect();
+
+                    assert_eq!(names, vec!["Monolith", "Odimm"]);
+                    assert_eq!(conf.under_dir, ".dots");
+                    assert_eq!(
+                        conf.keyfile,
+                        PathBuf::from("/home/fake/.config/manage_key")
+                    );
+                }
+                Err(err) => panic!("broken config {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn test_make_broken_config_unknown_key() {
+        setup_temp(fake_config_data, |_, fake_config_path| {
+            let fake_config = r#"
+                [base_vars]
+                under_dir = '.dots'
+                undr_dir = '.dots'
+
+                [targets.Monolith]
+                file_patterns = ['*_all', '*_M']
+                "#;
+
+            if let Err(err) = fs::write(fake_config_path, fake_config) {
+                panic!("{}", err);
+            }
+
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config with unknown key"),
+                Err(err) => {
+                    assert!(format!("{}", err).contains("undr_dir"), "{}", err)
+                }
+            }
+
+            let fake_config = r#"
+                stray = 1
+
+                [base_vars]
+                under_dir = '.dots'
+
+                [targets.Monolith]
+                file_patterns = ['*_all', '*_M']
+                "#;
+
+            fs::write(fake_config_path, fake_config).unwrap();
+
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config with stray key"),
+                Err(ManageError::Config { message, .. }) => {
+                    assert_eq!(message, "unknown key stray")
+                }
+                Err(err) => panic!("not a config error {}", err),
+            }
+
+            // the same check check-config puts at the key
+            let fake_config = r#"
+                [base_vars]
+                under_dir = '.dots'
+
+                [targets.Monolith]
+                file_patterns = ['*_all', '*_M']
+
+                [destinations]
+                nvim_all = '@conf/nvim'
+                "#;
+
+            fs::write(fake_config_path, fake_config).unwrap();
+
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config with bad values"),
+                Err(ManageError::Config { message, .. }) => assert!(
+                    message.starts_with("unknown root @conf for nvim_all"),
+                    "{}",
+                    message
+                ),
+                Err(err) => panic!("not a config error {}", err),
+            }
+
+            // a misspelled table is not taken as a target
+            let fake_config = r#"
+                [base_vars]
+                under_dir = '.dots'
+
+                [targest.Monolith]
+                file_patterns = ['*_all', '*_M']
+                "#;
+
+            fs::write(fake_config_path, fake_config).unwrap();
+
+            match make_config(fake_config_path) {
+                Ok(_) => panic!("made config with misspelled table"),
+                Err(ManageError::Config { message, .. }) => assert!(
+                    message.starts_with("unknown table targest"),
+                    "{}",
+                    message
+                ),
+                Err(err) => panic!("not a config error {}", err),
             }
         })
     }